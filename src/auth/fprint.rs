@@ -2,6 +2,8 @@ use futures::{select, StreamExt};
 use log::{error, info, warn};
 use zbus::{proxy, zvariant::OwnedObjectPath};
 
+use crate::auth::throttle;
+
 pub async fn check_fingerprint(await_wakeup: bool) -> bool {
     let Ok(connection) = zbus::Connection::system().await else {
         error!("Failed to connect to system bus.");
@@ -43,6 +45,9 @@ pub async fn check_fingerprint(await_wakeup: bool) -> bool {
 
     info!("Claimed fingerprint device. Starting verification");
     loop {
+        // Fingerprints are not read at all while attempts are refused
+        throttle::wait().await;
+
         if let Err(err) = device.verify_start("any").await {
             info!("Failed to start verification: {err}");
             return false;
@@ -57,7 +62,13 @@ pub async fn check_fingerprint(await_wakeup: bool) -> bool {
                 let _ = device.release().await;
                 return true;
             }
-            VerifyResult::NoMatch | VerifyResult::UnknownError | VerifyResult::UnexpectedWakeup => {
+            VerifyResult::NoMatch => {
+                throttle::record_failure();
+                if let Err(err) = device.verify_stop().await {
+                    info!("Failed to stop verification: {err}");
+                }
+            }
+            VerifyResult::UnknownError | VerifyResult::UnexpectedWakeup => {
                 if let Err(err) = device.verify_stop().await {
                     info!("Failed to stop verification: {err}");
                 }
@@ -1,3 +1,4 @@
 pub mod fprint;
 pub mod pam;
 pub mod signal;
+pub mod throttle;
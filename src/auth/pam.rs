@@ -1,62 +1,467 @@
-use std::ffi::{OsStr, OsString};
+use std::ffi::{c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::{fmt, mem, ptr};
 
-use log::{info, warn};
-use nonstick::{
-    AuthnFlags, ConversationAdapter, Result as PamResult, Transaction, TransactionBuilder,
+use futures::channel::{mpsc, oneshot};
+use futures::executor::block_on;
+use libpam_sys::{
+    pam_conv, pam_handle, pam_message, pam_response, PAM_ABORT, PAM_ACCT_EXPIRED,
+    PAM_AUTHINFO_UNAVAIL, PAM_AUTHTOK_ERR, PAM_AUTH_ERR, PAM_BUF_ERR, PAM_CHANGE_EXPIRED_AUTHTOK,
+    PAM_CONV_ERR, PAM_CRED_ERR, PAM_ERROR_MSG, PAM_MAXTRIES, PAM_NEW_AUTHTOK_REQD, PAM_PERM_DENIED,
+    PAM_PROMPT_ECHO_OFF, PAM_PROMPT_ECHO_ON, PAM_REFRESH_CRED, PAM_REINITIALIZE_CRED,
+    PAM_SERVICE_ERR, PAM_SUCCESS, PAM_SYSTEM_ERR, PAM_TEXT_INFO, PAM_USER_UNKNOWN,
 };
+use log::{error, info, warn};
+use nix::libc;
 
-struct UsernamePassConvo {
-    username: String,
-    password: String,
+use crate::config::{AuthConfig, CredentialsAction};
+
+/// Directories service files are read from, ones
+/// written by administrator take precedence
+const SERVICE_DIRS: [&str; 2] = ["/etc/pam.d", "/usr/lib/pam.d"];
+
+/// Service chosen by [`select_service`]
+static SERVICE: OnceLock<String> = OnceLock::new();
+
+/// Part of PAM conversation shown to user
+pub enum Message {
+    /// Question user must answer. Dropping `answer`
+    /// cancels authentication
+    Prompt {
+        text: String,
+        /// Whether answer may be shown as it is typed
+        echo: bool,
+        answer: oneshot::Sender<String>,
+    },
+    Info(String),
+    Error(String),
+}
+
+/// Forwards PAM conversation from blocking
+/// thread to the main loop
+struct UiConvo {
+    messages: mpsc::UnboundedSender<Message>,
+}
+
+impl UiConvo {
+    /// [`None`] if authentication was cancelled
+    fn ask(&self, text: String, echo: bool) -> Option<String> {
+        let (answer, answered) = oneshot::channel();
+        let prompt = Message::Prompt { text, echo, answer };
+
+        self.messages.unbounded_send(prompt).ok()?;
+        // PAM calls conversation synchronously, so the
+        // thread is blocked until user answers
+        block_on(answered).ok()
+    }
+
+    fn tell(&self, message: Message) {
+        // UI is gone only if session is already unlocked
+        let _ = self.messages.unbounded_send(message);
+    }
 }
 
-impl ConversationAdapter for UsernamePassConvo {
-    fn prompt(&self, _request: impl AsRef<OsStr>) -> PamResult<OsString> {
-        Ok(OsString::from(&self.username))
+/// Conversation function given to PAM, `appdata` is [`UiConvo`]
+///
+/// Each message is forwarded to UI in turn. Responses are
+/// allocated with `malloc` as PAM frees them itself
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *const *const pam_message,
+    resp: *mut *mut pam_response,
+    appdata: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || msg.is_null() || resp.is_null() || appdata.is_null() {
+        return PAM_CONV_ERR;
     }
+    // SAFETY: `appdata` is the `appdata_ptr` set by `Transaction::start`.
+    // It points into `Transaction::_convo`, which is boxed and dropped
+    // only after `pam_end`, and PAM calls conversation only before that
+    let convo = &*(appdata as *const UiConvo);
+    let count = num_msg as usize;
 
-    fn masked_prompt(&self, _request: impl AsRef<OsStr>) -> PamResult<OsString> {
-        Ok(OsString::from(&self.password))
+    // SAFETY: zeroed array is a valid array of `pam_response`, it is
+    // handed over to PAM on success and freed here on failure
+    let responses = libc::calloc(count, mem::size_of::<pam_response>()) as *mut pam_response;
+    if responses.is_null() {
+        return PAM_BUF_ERR;
     }
 
-    fn error_msg(&self, _message: impl AsRef<OsStr>) {}
+    for idx in 0..count {
+        // SAFETY: PAM passes `num_msg` valid messages. Linux-PAM
+        // passes an array of pointers to them, which is assumed here
+        let message = &**msg.add(idx);
+        let text = if message.msg.is_null() {
+            String::new()
+        } else {
+            // SAFETY: text of message is a C string owned by PAM
+            // that stays valid until conversation returns
+            CStr::from_ptr(message.msg).to_string_lossy().into_owned()
+        };
+
+        let answer = match message.msg_style {
+            PAM_PROMPT_ECHO_OFF => convo.ask(text, false),
+            PAM_PROMPT_ECHO_ON => convo.ask(text, true),
+            PAM_ERROR_MSG => {
+                warn!("PAM: {text}");
+                convo.tell(Message::Error(text));
+                continue;
+            }
+            PAM_TEXT_INFO => {
+                info!("PAM: {text}");
+                convo.tell(Message::Info(text));
+                continue;
+            }
+            style => {
+                warn!("Unsupported PAM message style {style}");
+                None
+            }
+        };
+
+        let Some(answer) = answer.and_then(|answer| CString::new(answer).ok()) else {
+            free_responses(responses, idx);
+            return PAM_CONV_ERR;
+        };
+        // SAFETY: `idx` is within the array allocated above. Copy is
+        // allocated with `malloc`, so PAM may free it with `free`
+        let copy = libc::strdup(answer.as_ptr());
+        if copy.is_null() {
+            free_responses(responses, idx);
+            return PAM_BUF_ERR;
+        }
+        (*responses.add(idx)).resp = copy;
+    }
+
+    // SAFETY: `resp` was checked to be non-null. From now on
+    // PAM owns the array and the answers in it
+    *resp = responses;
+    PAM_SUCCESS
+}
+
+/// Free first `count` responses and the array itself
+///
+/// # Safety
+///
+/// `responses` must be allocated by `malloc` with at least `count`
+/// elements, whose answers are either null or allocated by `malloc`
+unsafe fn free_responses(responses: *mut pam_response, count: usize) {
+    for idx in 0..count {
+        let answer = (*responses.add(idx)).resp;
+        if !answer.is_null() {
+            libc::free(answer as *mut c_void);
+        }
+    }
+    libc::free(responses as *mut c_void);
+}
+
+/// Return code of failed PAM call
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct ErrorCode(c_int);
 
-    fn info_msg(&self, _message: impl AsRef<OsStr>) {}
+type PamResult<T> = Result<T, ErrorCode>;
+
+impl fmt::Display for ErrorCode {
+    /// Same descriptions as `pam_strerror` gives, which
+    /// needs a handle that is not there if PAM failed to start
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.0 {
+            PAM_AUTH_ERR => "Authentication failure",
+            PAM_MAXTRIES => "Have exhausted maximum number of retries for service",
+            PAM_USER_UNKNOWN => "User not known to the underlying authentication module",
+            PAM_AUTHINFO_UNAVAIL => "Authentication service cannot retrieve authentication info",
+            PAM_NEW_AUTHTOK_REQD => "Authentication token is no longer valid; new one required",
+            PAM_ACCT_EXPIRED => "User account has expired",
+            PAM_PERM_DENIED => "Permission denied",
+            PAM_AUTHTOK_ERR => "Authentication token manipulation error",
+            PAM_CRED_ERR => "Failure setting user credentials",
+            PAM_CONV_ERR => "Conversation error",
+            PAM_BUF_ERR => "Memory buffer error",
+            PAM_SERVICE_ERR => "Error in service module",
+            PAM_SYSTEM_ERR => "System error",
+            PAM_ABORT => "Critical error - immediate abort",
+            code => return write!(f, "PAM error {code}"),
+        };
+        f.write_str(description)
+    }
+}
+
+/// PAM transaction driven through libpam directly, as the
+/// only way to call `pam_setcred`. Ended once dropped
+struct Transaction {
+    /// Valid from `pam_start` until `pam_end` in [`Drop`]
+    handle: *mut pam_handle,
+    /// Result of the last call, reported to modules on end
+    last_result: c_int,
+    /// Must outlive the handle, PAM implementations
+    /// may keep pointers to both
+    _conv: Box<pam_conv>,
+    _convo: Box<UiConvo>,
 }
 
-/// This function is blocking. Run it using [`gtk::gio::spawn_blocking`]
-pub fn check_password(password: String) -> bool {
+impl Transaction {
+    fn start(service: &str, username: &str, convo: UiConvo) -> PamResult<Self> {
+        let service = CString::new(service).map_err(|_| ErrorCode(PAM_SERVICE_ERR))?;
+        let username = CString::new(username).map_err(|_| ErrorCode(PAM_USER_UNKNOWN))?;
+
+        // Both are boxed, so pointers given to PAM stay
+        // valid once they are moved into transaction
+        let convo = Box::new(convo);
+        let mut conv = Box::new(pam_conv {
+            conv: converse,
+            appdata_ptr: &*convo as *const UiConvo as *mut c_void,
+        });
+
+        let mut handle = ptr::null_mut();
+        // SAFETY: strings are valid for the call, PAM copies them.
+        // `conv` and `appdata_ptr` it points to are kept alive by
+        // the transaction until after `pam_end`
+        let result = unsafe {
+            libpam_sys::pam_start(service.as_ptr(), username.as_ptr(), &mut *conv, &mut handle)
+        };
+        if handle.is_null() {
+            return Err(ErrorCode(result));
+        }
+
+        let mut txn = Self {
+            handle,
+            last_result: PAM_SUCCESS,
+            _conv: conv,
+            _convo: convo,
+        };
+        txn.check(result)?;
+
+        Ok(txn)
+    }
+
+    fn authenticate(&mut self) -> PamResult<()> {
+        // SAFETY: handle is valid until transaction is dropped
+        let result = unsafe { libpam_sys::pam_authenticate(self.handle, 0) };
+        self.check(result)
+    }
+
+    fn account_management(&mut self) -> PamResult<()> {
+        // SAFETY: handle is valid until transaction is dropped
+        let result = unsafe { libpam_sys::pam_acct_mgmt(self.handle, 0) };
+        self.check(result)
+    }
+
+    fn change_expired_authtok(&mut self) -> PamResult<()> {
+        // SAFETY: handle is valid until transaction is dropped
+        let result = unsafe { libpam_sys::pam_chauthtok(self.handle, PAM_CHANGE_EXPIRED_AUTHTOK) };
+        self.check(result)
+    }
+
+    fn setcred(&mut self, flags: c_int) -> PamResult<()> {
+        // SAFETY: handle is valid until transaction is dropped
+        let result = unsafe { libpam_sys::pam_setcred(self.handle, flags) };
+        self.check(result)
+    }
+
+    fn check(&mut self, result: c_int) -> PamResult<()> {
+        self.last_result = result;
+        if result == PAM_SUCCESS {
+            Ok(())
+        } else {
+            Err(ErrorCode(result))
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        // SAFETY: handle is valid and is not used after this.
+        // Conversation is dropped only after this returns
+        unsafe { libpam_sys::pam_end(self.handle, self.last_result) };
+    }
+}
+
+/// Why session was not unlocked
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Failure {
+    /// PAM could not be started
+    Unavailable,
+    /// User was not authenticated, e.g. password was wrong
+    Authentication,
+    /// User is authenticated, but their account may not be used
+    /// or its expired password was not changed
+    Account,
+}
+
+/// Choose PAM service used for the rest of the session,
+/// falling back to alternative one if configured service
+/// is not installed
+///
+/// [`None`] if neither is installed. PAM would use `other`
+/// service then, which usually denies everyone, so
+/// session must not be locked
+pub fn select_service(config: &AuthConfig) -> Option<&'static str> {
+    if let Some(service) = SERVICE.get() {
+        return Some(service);
+    }
+
+    let service = if service_installed(&config.service) {
+        config.service.clone()
+    } else {
+        error!(
+            "PAM service \"{}\" is not installed, {}/{} is missing",
+            config.service, SERVICE_DIRS[0], config.service
+        );
+        match &config.fallback_service {
+            Some(fallback) if service_installed(fallback) => {
+                error!("Falling back to PAM service \"{fallback}\"");
+                fallback.clone()
+            }
+            Some(fallback) => {
+                error!("Fallback PAM service \"{fallback}\" is not installed either");
+                return None;
+            }
+            None => return None,
+        }
+    };
+
+    Some(SERVICE.get_or_init(|| service))
+}
+
+fn service_installed(service: &str) -> bool {
+    // Name is joined to a path, it must not point elsewhere
+    if service.is_empty() || service.contains('/') {
+        return false;
+    }
+
+    SERVICE_DIRS
+        .iter()
+        .any(|dir| Path::new(dir).join(service).is_file())
+}
+
+/// Authenticate current user and check their account,
+/// asking questions of PAM modules through `messages`
+///
+/// Expired password is changed right away. Reason of
+/// failure is reported through `messages` as well.
+/// Once user is authenticated credentials of PAM modules
+/// are handled according to `credentials`
+///
+/// Service must have been chosen with [`select_service`].
+/// This function is blocking. Run it in a separate thread
+pub fn authenticate(
+    messages: mpsc::UnboundedSender<Message>,
+    credentials: CredentialsAction,
+) -> Result<(), Failure> {
+    run(messages, credentials, true)
+}
+
+/// Check account of current user, who has been authenticated
+/// by other means such as fingerprint, and handle credentials
+/// according to `credentials`
+///
+/// Nothing can be asked, so expired password has to be
+/// changed through [`authenticate`]
+///
+/// This function is blocking. Run it in a separate thread
+pub fn check_account(credentials: CredentialsAction) -> Result<(), Failure> {
+    // Receiver is dropped, so questions of PAM fail right away
+    let (messages, _) = mpsc::unbounded();
+    run(messages, credentials, false)
+}
+
+fn run(
+    messages: mpsc::UnboundedSender<Message>,
+    credentials: CredentialsAction,
+    authenticate: bool,
+) -> Result<(), Failure> {
     info!("Starting pam authentification.");
     let Some(username) =
         users::get_current_username().map(|os_string| os_string.to_string_lossy().into_owned())
     else {
         warn!("Failed to get current user name. Session won't be unlocked.");
-        return false;
+        return Err(Failure::Unavailable);
     };
 
     info!("Current user is \"{username}\".");
 
-    let credentials = UsernamePassConvo {
-        username: username.clone(),
-        password,
+    let reports = messages.clone();
+    let report = |message: Message| {
+        let _ = reports.unbounded_send(message);
     };
 
-    let Ok(mut txn) = TransactionBuilder::new_with_service("shackle")
-        .username(username)
-        .build(credentials.into_conversation())
-    else {
+    let Some(service) = SERVICE.get() else {
+        warn!("PAM service is not chosen. Session won't be unlocked.");
+        return Err(Failure::Unavailable);
+    };
+
+    let Ok(mut txn) = Transaction::start(service, &username, UiConvo { messages }) else {
         warn!("Failed to initialize PAM client. Session won't be unlocked.");
-        return false;
+        return Err(Failure::Unavailable);
     };
 
-    match txn.authenticate(AuthnFlags::empty()) {
-        Ok(_) => {
-            info!("Password correct.");
-            true
+    if authenticate {
+        if let Err(err) = txn.authenticate() {
+            info!("Authentication failed: {err}.");
+            report(Message::Error(authentication_error(err)));
+            return Err(Failure::Authentication);
         }
-        Err(_) => {
-            info!("Password incorrect.");
-            false
+        info!("Authentication successful.");
+    }
+
+    match txn.account_management() {
+        Ok(()) => (),
+        Err(ErrorCode(PAM_NEW_AUTHTOK_REQD)) if !authenticate => {
+            warn!("Password expired, it has to be changed before unlocking.");
+            return Err(Failure::Account);
+        }
+        Err(ErrorCode(PAM_NEW_AUTHTOK_REQD)) => {
+            info!("Password expired, changing it.");
+            report(Message::Info(
+                "Your password has expired and must be changed".to_owned(),
+            ));
+
+            // Only expired password is changed, others are kept
+            if let Err(err) = txn.change_expired_authtok() {
+                info!("Failed to change password: {err}.");
+                report(Message::Error(format!("Password was not changed: {err}")));
+                return Err(Failure::Account);
+            }
+            info!("Password changed.");
         }
+        Err(err) => {
+            info!("Account is not available: {err}.");
+            report(Message::Error(account_error(err)));
+            return Err(Failure::Account);
+        }
+    }
+
+    let flags = match credentials {
+        CredentialsAction::Keep => None,
+        CredentialsAction::Refresh => Some(PAM_REFRESH_CRED),
+        CredentialsAction::Reinitialize => Some(PAM_REINITIALIZE_CRED),
+    };
+    if let Some(flags) = flags {
+        // User is already authenticated, stale tickets
+        // are no reason to keep session locked
+        match txn.setcred(flags) {
+            Ok(()) => info!("Credentials refreshed."),
+            Err(err) => warn!("Failed to refresh credentials: {err}."),
+        }
+    }
+
+    Ok(())
+}
+
+fn authentication_error(err: ErrorCode) -> String {
+    match err.0 {
+        PAM_AUTH_ERR => "Authentication failed".to_owned(),
+        PAM_MAXTRIES => "Too many failed attempts".to_owned(),
+        PAM_USER_UNKNOWN => "User is not known to authentication service".to_owned(),
+        PAM_AUTHINFO_UNAVAIL => "Authentication service is not available".to_owned(),
+        _ => format!("Authentication failed: {err}"),
+    }
+}
+
+fn account_error(err: ErrorCode) -> String {
+    match err.0 {
+        PAM_ACCT_EXPIRED => "Your account has expired".to_owned(),
+        PAM_PERM_DENIED => "Your account is not allowed to log in".to_owned(),
+        _ => format!("Your account is not available: {err}"),
     }
 }
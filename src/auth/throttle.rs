@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use gtk::glib;
+use log::{info, warn};
+
+use crate::config::{config, ThrottleConfig};
+use crate::dirs::state_dir;
+
+/// Consecutive failed attempts of either password or fingerprint
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    last: Option<SystemTime>,
+    /// Time after `last` during which attempts are refused
+    delay: Duration,
+}
+
+impl Failures {
+    fn remaining(&self) -> Option<Duration> {
+        // If clock went backwards whole delay is waited out
+        // again, but never more than that
+        let elapsed = SystemTime::now()
+            .duration_since(self.last?)
+            .unwrap_or_default();
+        self.delay
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+thread_local! {
+    /// Read from state file on first use, so that restarting
+    /// locker does not reset failed attempts
+    static FAILURES: RefCell<Failures> = RefCell::new(read_failures());
+    static LISTENERS: RefCell<Vec<Box<dyn Fn()>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Time left until next attempt is allowed,
+/// [`None`] if it is allowed right away
+pub fn remaining() -> Option<Duration> {
+    FAILURES.with_borrow(Failures::remaining)
+}
+
+/// Wait until next attempt is allowed
+pub async fn wait() {
+    while let Some(remaining) = remaining() {
+        glib::timeout_future(remaining).await;
+    }
+}
+
+/// Count failed attempt, delaying the next one
+/// if there were too many of them in a row
+pub fn record_failure() {
+    let delay = FAILURES.with_borrow_mut(|failures| {
+        failures.count = failures.count.saturating_add(1);
+        failures.last = Some(SystemTime::now());
+        failures.delay = delay_after(&config().auth.throttle, failures.count);
+        write_failures(failures);
+
+        info!("{} failed attempts in a row", failures.count);
+        failures.delay
+    });
+
+    if !delay.is_zero() {
+        info!("Refusing attempts for {} s", delay.as_secs_f64());
+        LISTENERS.with_borrow(|listeners| listeners.iter().for_each(|listener| listener()));
+    }
+}
+
+/// Forget failed attempts once session is unlocked
+pub fn reset_failures() {
+    FAILURES.with_borrow_mut(|failures| *failures = Failures::default());
+
+    let Some(file) = failures_file() else {
+        return;
+    };
+    match fs::remove_file(&file) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => warn!(
+            "Failed to remove failed attempts from {}: {err}",
+            file.to_string_lossy()
+        ),
+    }
+}
+
+/// Call `listener` every time attempts start being refused
+pub fn connect_throttled(listener: impl Fn() + 'static) {
+    LISTENERS.with_borrow_mut(|listeners| listeners.push(Box::new(listener)));
+}
+
+/// Delay after `count` failed attempts in a row
+fn delay_after(config: &ThrottleConfig, count: u32) -> Duration {
+    let Some(index) = count.checked_sub(config.free_attempts.saturating_add(1)) else {
+        return Duration::ZERO;
+    };
+    let Some(&seconds) = config.delays.get(index as usize).or(config.delays.last()) else {
+        return Duration::ZERO;
+    };
+
+    Duration::try_from_secs_f64(seconds).unwrap_or_else(|_| {
+        warn!("Ignoring auth.throttle.delays: {seconds} is not a valid delay");
+        Duration::ZERO
+    })
+}
+
+fn failures_file() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("failed-attempts"))
+}
+
+/// Stored as count, time of last failure in
+/// seconds since epoch and delay in seconds
+fn read_failures() -> Failures {
+    let Some(data) = failures_file().and_then(|file| fs::read_to_string(file).ok()) else {
+        return Failures::default();
+    };
+
+    parse_failures(&data).unwrap_or_else(|| {
+        warn!("Ignoring malformed failed attempts file");
+        Failures::default()
+    })
+}
+
+fn parse_failures(data: &str) -> Option<Failures> {
+    let [count, last, delay] = data.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    Some(Failures {
+        count: count.parse().ok()?,
+        last: Some(UNIX_EPOCH + Duration::try_from_secs_f64(last.parse().ok()?).ok()?),
+        delay: Duration::try_from_secs_f64(delay.parse().ok()?).ok()?,
+    })
+}
+
+fn write_failures(failures: &Failures) {
+    let Some(file) = failures_file() else {
+        return;
+    };
+
+    let result = file
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&file, format_failures(failures)));
+    if let Err(err) = result {
+        warn!(
+            "Failed to save failed attempts to {}: {err}",
+            file.to_string_lossy()
+        );
+    }
+}
+
+fn format_failures(failures: &Failures) -> String {
+    // Sub-second precision, so that delay is not cut
+    // short by up to a second when read back
+    let last = failures
+        .last
+        .and_then(|last| last.duration_since(UNIX_EPOCH).ok())
+        .map_or(0.0, |last| last.as_secs_f64());
+    format!(
+        "{} {last} {}\n",
+        failures.count,
+        failures.delay.as_secs_f64()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(free_attempts: u32, delays: &[f64]) -> ThrottleConfig {
+        ThrottleConfig {
+            free_attempts,
+            delays: delays.to_vec(),
+        }
+    }
+
+    #[test]
+    fn free_attempts_are_not_delayed() {
+        let config = throttle(3, &[1.0, 5.0]);
+        for count in 0..=3 {
+            assert_eq!(delay_after(&config, count), Duration::ZERO);
+        }
+        assert_eq!(delay_after(&config, 4), Duration::from_secs(1));
+        assert_eq!(delay_after(&config, 5), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn last_delay_repeats() {
+        let config = throttle(0, &[1.0, 30.0]);
+        assert_eq!(delay_after(&config, 2), Duration::from_secs(30));
+        assert_eq!(delay_after(&config, 3), Duration::from_secs(30));
+        assert_eq!(delay_after(&config, u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn no_delays_disable_throttling() {
+        let config = throttle(0, &[]);
+        assert_eq!(delay_after(&config, 1), Duration::ZERO);
+        assert_eq!(delay_after(&config, 100), Duration::ZERO);
+    }
+
+    #[test]
+    fn failures_round_trip() {
+        let failures = Failures {
+            count: 7,
+            last: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_250)),
+            delay: Duration::from_millis(1500),
+        };
+        let parsed = parse_failures(&format_failures(&failures)).unwrap();
+
+        assert_eq!(parsed.count, failures.count);
+        let last = parsed.last.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        let expected = failures.last.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        assert!(last.abs_diff(expected) < Duration::from_micros(1));
+        assert_eq!(parsed.delay, failures.delay);
+    }
+
+    #[test]
+    fn malformed_failures_are_rejected() {
+        assert!(parse_failures("").is_none());
+        assert!(parse_failures("3 1700000000").is_none());
+        assert!(parse_failures("three 1700000000 5").is_none());
+        assert!(parse_failures("3 -1 5").is_none());
+    }
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::time::{Duration, SystemTime};
+
+use gtk::gdk;
+use gtk::gdk_pixbuf::{PixbufAnimation, PixbufAnimationIter};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+
+/// Frames with zero or tiny delay are shown
+/// for this long, same as browsers do
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct AnimatedImage {
+        pub iter: RefCell<Option<PixbufAnimationIter>>,
+        pub frame: RefCell<Option<gdk::Texture>>,
+        pub timeout: RefCell<Option<glib::SourceId>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AnimatedImage {
+        const NAME: &'static str = "ShackleAnimatedImage";
+        type Type = super::AnimatedImage;
+        type Interfaces = (gdk::Paintable,);
+    }
+
+    impl ObjectImpl for AnimatedImage {
+        fn dispose(&self) {
+            if let Some(timeout) = self.timeout.take() {
+                timeout.remove();
+            }
+        }
+    }
+
+    impl PaintableImpl for AnimatedImage {
+        fn intrinsic_width(&self) -> i32 {
+            self.frame
+                .borrow()
+                .as_ref()
+                .map_or(0, |frame| frame.width())
+        }
+
+        fn intrinsic_height(&self) -> i32 {
+            self.frame
+                .borrow()
+                .as_ref()
+                .map_or(0, |frame| frame.height())
+        }
+
+        fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+            if let Some(frame) = self.frame.borrow().as_ref() {
+                frame.snapshot(snapshot, width, height);
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    /// Paintable playing multi-frame image decoded by gdk-pixbuf
+    pub struct AnimatedImage(ObjectSubclass<imp::AnimatedImage>)
+        @implements gdk::Paintable;
+}
+
+impl AnimatedImage {
+    pub fn new(animation: &PixbufAnimation) -> Self {
+        let image: Self = glib::Object::new();
+        image
+            .imp()
+            .iter
+            .replace(Some(animation.iter(Some(SystemTime::now()))));
+        image.show_frame();
+        image
+    }
+
+    /// Show current frame and schedule the next one
+    fn show_frame(&self) {
+        let imp = self.imp();
+        let Some(iter) = imp.iter.borrow().clone() else {
+            return;
+        };
+
+        imp.frame
+            .replace(Some(gdk::Texture::for_pixbuf(&iter.pixbuf())));
+        self.invalidate_contents();
+
+        // No delay means this frame is the last one
+        let Some(delay) = iter.delay_time() else {
+            return;
+        };
+
+        let timeout = glib::timeout_add_local_once(
+            delay.max(MIN_FRAME_DELAY),
+            clone!(
+                #[weak(rename_to = image)]
+                self,
+                move || {
+                    // Source is already destroyed once fired
+                    let _ = image.imp().timeout.take();
+                    if let Some(iter) = image.imp().iter.borrow().as_ref() {
+                        iter.advance(SystemTime::now());
+                    }
+                    image.show_frame();
+                }
+            ),
+        );
+        imp.timeout.replace(Some(timeout));
+    }
+}
@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::future::{ready, FutureExt, LocalBoxFuture, WeakShared};
+use gtk::gdk;
+use gtk::gdk_pixbuf::Pixbuf;
+use gtk::gio;
+use gtk::glib;
+use gtk::prelude::*;
+use log::{info, warn};
+
+use crate::dirs::cache_dir;
+
+/// Total size of downscaled images kept on disk, the
+/// oldest ones are removed once there are more
+const MAX_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+/// Quality of cached images without transparency
+const JPEG_QUALITY: &str = "90";
+/// Formats cached images may be saved in
+const CACHE_EXTENSIONS: [&str; 2] = ["jpg", "png"];
+
+/// Modification time and size of file, identifying its version
+pub type Stamp = (SystemTime, u64);
+
+type TextureFuture = LocalBoxFuture<'static, Option<gdk::Texture>>;
+/// File and size it is downscaled to
+type TextureKey = (PathBuf, Option<(i32, i32)>);
+
+/// Texture of a file being loaded or already loaded
+enum Entry {
+    Loading(WeakShared<TextureFuture>),
+    Loaded(glib::WeakRef<gdk::Texture>),
+}
+
+thread_local! {
+    /// Textures by file and size, so that monitors showing
+    /// the same file do not decode it again. Only textures
+    /// still in use are kept
+    static TEXTURES: RefCell<HashMap<TextureKey, Entry>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Texture of image at `path`, downscaled to `size` if set
+///
+/// Downscaled images are cached on disk, `stamp` is used to
+/// detect changes of the original file
+pub async fn texture(
+    path: &Path,
+    stamp: Option<Stamp>,
+    size: Option<(i32, i32)>,
+) -> Option<gdk::Texture> {
+    let key = (path.to_owned(), size);
+
+    let existing = TEXTURES.with_borrow(|textures| match textures.get(&key)? {
+        Entry::Loading(loading) => loading.upgrade(),
+        Entry::Loaded(loaded) => Some(ready(loaded.upgrade()).boxed_local().shared()),
+    });
+    if let Some(existing) = existing {
+        if let Some(texture) = existing.await {
+            return Some(texture);
+        }
+    }
+
+    let loading = decode(path.to_owned(), stamp, size).boxed_local().shared();
+    TEXTURES.with_borrow_mut(|textures| {
+        let weak = loading.downgrade().expect("future is not polled yet");
+        textures.insert(key.clone(), Entry::Loading(weak));
+    });
+
+    let texture = loading.await;
+
+    TEXTURES.with_borrow_mut(|textures| {
+        // Forget entries of textures no longer shown
+        textures.retain(|_, entry| match entry {
+            Entry::Loading(loading) => loading.upgrade().is_some(),
+            Entry::Loaded(loaded) => loaded.upgrade().is_some(),
+        });
+        if let Some(texture) = &texture {
+            textures.insert(key, Entry::Loaded(texture.downgrade()));
+        }
+    });
+
+    texture
+}
+
+async fn decode(
+    path: PathBuf,
+    stamp: Option<Stamp>,
+    size: Option<(i32, i32)>,
+) -> Option<gdk::Texture> {
+    let cached = size
+        .zip(stamp)
+        .and_then(|(size, stamp)| cache_file(&path, stamp, size));
+
+    if let Some(cached) = &cached {
+        for extension in CACHE_EXTENSIONS {
+            let file = cached.with_extension(extension);
+            // Missing file is the usual case, not worth reporting
+            if let Ok(pixbuf) = read_pixbuf(&file, None).await {
+                info!("Using cached {}", file.to_string_lossy());
+                return Some(gdk::Texture::for_pixbuf(&pixbuf));
+            }
+        }
+    }
+
+    let pixbuf = match read_pixbuf(&path, size).await {
+        Ok(pixbuf) => pixbuf,
+        Err(err) => {
+            warn!("Failed to load {}: {err}", path.to_string_lossy());
+            return None;
+        }
+    };
+    // Photos are often stored rotated with EXIF tag telling so
+    let pixbuf = pixbuf.apply_embedded_orientation().unwrap_or(pixbuf);
+
+    if let Some(cached) = cached {
+        save(&pixbuf, cached).await;
+    }
+
+    Some(gdk::Texture::for_pixbuf(&pixbuf))
+}
+
+/// Decoding happens in GIO worker thread. If `size` is set
+/// image is scaled while decoding, which saves memory as well
+async fn read_pixbuf(path: &Path, size: Option<(i32, i32)>) -> Result<Pixbuf, glib::Error> {
+    let stream = gio::File::for_path(path)
+        .read_future(glib::Priority::DEFAULT)
+        .await?;
+
+    match size {
+        Some((width, height)) => {
+            Pixbuf::from_stream_at_scale_future(&stream, width, height, false).await
+        }
+        None => Pixbuf::from_stream_future(&stream).await,
+    }
+}
+
+/// Location of downscaled image without extension. Version
+/// and size are spelled out, so that names never depend on
+/// hasher of a particular Rust release
+fn cache_file(path: &Path, (modified, len): Stamp, (width, height): (i32, i32)) -> Option<PathBuf> {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |modified| modified.as_nanos());
+    let name = format!(
+        "{:016x}-{modified}-{len}-{width}x{height}",
+        fnv1a(path.as_os_str().as_bytes())
+    );
+
+    cache_dir().map(|dir| dir.join(name))
+}
+
+/// 64-bit FNV-1a, stable unlike [`std::hash::DefaultHasher`]
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+async fn save(pixbuf: &Pixbuf, file: PathBuf) {
+    // Photos are far smaller as JPEG and decode faster
+    let (format, extension, options): (&str, &str, &[(&str, &str)]) = if pixbuf.has_alpha() {
+        ("png", "png", &[])
+    } else {
+        ("jpeg", "jpg", &[("quality", JPEG_QUALITY)])
+    };
+    let file = file.with_extension(extension);
+
+    // Cache directory may be on slow storage too
+    let dir = file.parent().map(Path::to_owned);
+    let created = gio::spawn_blocking({
+        let dir = dir.clone();
+        move || dir.map_or(Ok(()), fs::create_dir_all)
+    })
+    .await;
+    if let Ok(Err(err)) = created {
+        warn!("Failed to create cache directory: {err}");
+        return;
+    }
+
+    // Replace writes to temporary file first, so that
+    // partially written file never ends up in cache
+    let result = async {
+        let stream = gio::File::for_path(&file)
+            .replace_future(
+                None,
+                false,
+                gio::FileCreateFlags::PRIVATE,
+                glib::Priority::LOW,
+            )
+            .await?;
+        pixbuf
+            .save_to_streamv_future(&stream, format, options)
+            .await?;
+        stream.close_future(glib::Priority::LOW).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => info!("Cached downscaled image in {}", file.to_string_lossy()),
+        Err(err) => warn!("Failed to cache {}: {err}", file.to_string_lossy()),
+    }
+
+    if let Some(dir) = dir {
+        let _ = gio::spawn_blocking(move || trim_cache(&dir)).await;
+    }
+}
+
+/// Remove the oldest files until cache fits into [`MAX_CACHE_BYTES`]
+fn trim_cache(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flat_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+
+    // Newest first, everything past the limit goes
+    files.sort_by_key(|(modified, _, _)| Reverse(*modified));
+    let mut total = 0;
+    for (_, len, file) in files {
+        total += len;
+        if total > MAX_CACHE_BYTES {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::f64::consts::SQRT_2;
+
+use gtk::gdk;
+use gtk::graphene;
+use gtk::gsk;
+use gtk::prelude::*;
+use log::warn;
+
+use crate::config::{BackgroundConfig, GradientKind};
+
+/// Solid color or gradient painted behind background
+#[derive(Clone)]
+pub enum Fill {
+    Color(gdk::RGBA),
+    Linear {
+        angle: f64,
+        stops: Vec<gsk::ColorStop>,
+    },
+    Radial {
+        stops: Vec<gsk::ColorStop>,
+    },
+}
+
+impl Fill {
+    /// Returns [`None`] if neither color nor gradient is
+    /// configured, or none of configured colors are valid
+    pub fn from_config(config: &BackgroundConfig) -> Option<Self> {
+        if let Some(gradient) = &config.gradient {
+            let colors: Vec<gdk::RGBA> = gradient
+                .colors
+                .iter()
+                .filter_map(|color| parse_color("background.gradient.colors", color))
+                .collect();
+
+            match colors.as_slice() {
+                [] => (),
+                [color] => return Some(Self::Color(*color)),
+                _ => {
+                    let last = (colors.len() - 1) as f32;
+                    let stops = colors
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, color)| gsk::ColorStop::new(idx as f32 / last, *color))
+                        .collect();
+
+                    return Some(match gradient.kind {
+                        GradientKind::Linear => Self::Linear {
+                            angle: gradient.angle,
+                            stops,
+                        },
+                        GradientKind::Radial => Self::Radial { stops },
+                    });
+                }
+            }
+        }
+
+        config
+            .color
+            .as_ref()
+            .and_then(|color| parse_color("background.color", color))
+            .map(Self::Color)
+    }
+
+    pub fn snapshot(&self, snapshot: &gtk::Snapshot, width: f64, height: f64) {
+        let bounds = graphene::Rect::new(0.0, 0.0, width as f32, height as f32);
+
+        match self {
+            Self::Color(color) => snapshot.append_color(color, &bounds),
+            Self::Linear { angle, stops } => {
+                // Gradient line passes through the center and is long
+                // enough for corners to get first and last colors
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let length = (width * sin).abs() + (height * cos).abs();
+                let (dx, dy) = (sin * length / 2.0, -cos * length / 2.0);
+                let (cx, cy) = (width / 2.0, height / 2.0);
+
+                snapshot.append_linear_gradient(
+                    &bounds,
+                    &graphene::Point::new((cx - dx) as f32, (cy - dy) as f32),
+                    &graphene::Point::new((cx + dx) as f32, (cy + dy) as f32),
+                    stops,
+                );
+            }
+            Self::Radial { stops } => snapshot.append_radial_gradient(
+                &bounds,
+                &graphene::Point::new((width / 2.0) as f32, (height / 2.0) as f32),
+                (width / 2.0 * SQRT_2) as f32,
+                (height / 2.0 * SQRT_2) as f32,
+                0.0,
+                1.0,
+                stops,
+            ),
+        }
+    }
+}
+
+fn parse_color(key: &str, color: &str) -> Option<gdk::RGBA> {
+    match gdk::RGBA::parse(color) {
+        Ok(color) => Some(color),
+        Err(_) => {
+            warn!("Ignoring {key}: \"{color}\" is not a valid color");
+            None
+        }
+    }
+}
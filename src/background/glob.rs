@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `path` contains `*` or `?` wildcards
+pub fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?'])
+}
+
+/// Paths matching `pattern` in sorted order
+///
+/// Only `*` (any sequence of characters) and `?` (any single character)
+/// are supported, neither of them matches `/`. Hidden files are
+/// matched only if pattern component starts with `.` explicitly
+pub fn expand(pattern: &Path) -> Vec<PathBuf> {
+    let mut matches = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let Component::Normal(name) = component else {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        };
+
+        let name = name.to_string_lossy();
+        if !name.contains(['*', '?']) {
+            for path in &mut matches {
+                path.push(name.as_ref());
+            }
+            continue;
+        }
+
+        matches = matches
+            .iter()
+            .flat_map(|dir| children_matching(dir, &name))
+            .collect();
+    }
+
+    matches.retain(|path| path.exists());
+    matches.sort();
+    matches
+}
+
+fn children_matching(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let read_dir = if dir.as_os_str().is_empty() {
+        fs::read_dir(".")
+    } else {
+        fs::read_dir(dir)
+    };
+    let Ok(entries) = read_dir else {
+        return Vec::new();
+    };
+
+    entries
+        .flat_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            (pattern.starts_with('.') || !name.starts_with('.')) && wildcard_match(pattern, &name)
+        })
+        .map(|entry| dir.join(entry.file_name()))
+        .collect()
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and of name character it
+    // currently matches up to, to backtrack on mismatch
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(wildcard_match("*.jpg", "forest.jpg"));
+        assert!(wildcard_match("*.jpg", ".jpg"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("*.jpg", "forest.png"));
+        assert!(!wildcard_match("*.jpg", "forest.jpg.png"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(wildcard_match("a*b*c", "abc"));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(wildcard_match("a*b*c", "abcbc"));
+        assert!(wildcard_match("a*b*c", "abbbc"));
+        assert!(!wildcard_match("a*b*c", "acb"));
+        assert!(!wildcard_match("a*b*c", "abcb"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(wildcard_match("img?.png", "img1.png"));
+        assert!(!wildcard_match("img?.png", "img.png"));
+        assert!(!wildcard_match("img?.png", "img12.png"));
+        assert!(wildcard_match("??", "ab"));
+    }
+
+    #[test]
+    fn expand_skips_hidden_files_unless_asked() {
+        let dir = std::env::temp_dir().join(format!("shackle-glob-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["a.jpg", "b.png", ".hidden.jpg", "sub/c.jpg"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        assert_eq!(expand(&dir.join("*.jpg")), vec![dir.join("a.jpg")]);
+        assert_eq!(expand(&dir.join(".*.jpg")), vec![dir.join(".hidden.jpg")]);
+        assert_eq!(
+            expand(&dir.join("*")),
+            vec![dir.join("a.jpg"), dir.join("b.png"), dir.join("sub")]
+        );
+        assert_eq!(expand(&dir.join("s?b/*.jpg")), vec![dir.join("sub/c.jpg")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use gtk::gdk;
+use gtk::gdk_pixbuf::{Pixbuf, PixbufAnimation};
+use gtk::gio;
+use gtk::glib;
+use gtk::prelude::*;
+use itertools::Itertools;
+use log::{debug, info, warn};
+use rand::seq::IndexedRandom;
+
+use crate::config::config;
+use crate::dirs::state_dir;
+
+use super::animation::AnimatedImage;
+use super::cache::{texture, Stamp};
+use super::glob::{expand, is_pattern};
+use super::matching::{best_fitting, resolve_variants, Target};
+use super::stream::VideoStream;
+
+/// Amount of data read from the beginning of file
+/// to detect its content type
+const SNIFF_LENGTH: u64 = 4096;
+
+/// Formats gdk-pixbuf may decode as multiple frames.
+/// Other images are always loaded as a single texture
+const ANIMATED_TYPES: [&str; 4] = ["image/gif", "image/webp", "image/avif", "image/apng"];
+
+/// Number of recently shown backgrounds remembered
+const HISTORY_LENGTH: usize = 64;
+
+/// Each pick reads and writes history under this lock,
+/// so that concurrent picks do not lose each other's entries
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Copy)]
+enum Media {
+    Image,
+    Video,
+}
+
+/// Image or video that can be shown on background
+#[derive(Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    content_type: glib::GString,
+    media: Media,
+    /// Relative probability of being picked
+    weight: f64,
+    /// Dimensions of image, videos are not probed
+    size: Option<(i32, i32)>,
+    stamp: Option<Stamp>,
+}
+
+impl Candidate {
+    /// Returns [`None`] if file can not be read
+    /// or is neither image nor video
+    pub fn new(path: PathBuf) -> Option<Self> {
+        let content_type = content_type(&path)?;
+        let Some(media) = media(&content_type) else {
+            debug!(
+                "Skipping {}: {content_type} is neither image nor video",
+                path.to_string_lossy()
+            );
+            return None;
+        };
+        // Reads only image header
+        let size = match media {
+            Media::Image => match Pixbuf::file_info(&path) {
+                Some((_, width, height)) => Some((width, height)),
+                None => {
+                    // Picking it would leave monitor blank
+                    warn!(
+                        "Skipping {}: no image loader for {content_type}",
+                        path.to_string_lossy()
+                    );
+                    return None;
+                }
+            },
+            Media::Video => None,
+        }
+        .filter(|&(width, height)| width > 0 && height > 0);
+        let stamp = fs::metadata(&path)
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+
+        Some(Self {
+            path,
+            content_type,
+            media,
+            weight: 1.0,
+            size,
+            stamp,
+        })
+    }
+
+    pub fn size(&self) -> Option<(i32, i32)> {
+        self.size
+    }
+
+    /// Decoding happens in GIO worker threads, so that
+    /// main loop is never blocked
+    ///
+    /// Still images larger than needed to cover `scale_to`
+    /// are downscaled
+    pub async fn load(&self, scale_to: Option<Target>) -> Option<gdk::Paintable> {
+        info!(
+            "Using {} ({}) as background",
+            self.path.to_string_lossy(),
+            self.content_type
+        );
+
+        match self.media {
+            Media::Image => load_image(self, scale_to).await,
+            Media::Video => load_video(&self.path, scale_to),
+        }
+    }
+}
+
+/// Whether `sources` name exactly one file rather
+/// than a set of files to choose from
+fn is_single_file(sources: &[PathBuf]) -> bool {
+    match sources {
+        [source] => !is_pattern(source) && !source.is_dir(),
+        _ => false,
+    }
+}
+
+/// Supported files from `sources`
+///
+/// Sources may be files, directories, which are scanned
+/// recursively, or wildcard patterns matching either of them.
+/// Files inside subfolders listed in `weights` get weight of
+/// the deepest listed subfolder, all other have weight of 1.
+/// Of resolution variants of the same image only the one
+/// suiting `target` best is kept
+pub fn scan(sources: &[PathBuf], weights: &HashMap<PathBuf, f64>, target: Option<Target>) -> Scan {
+    let weights: HashMap<&Path, f64> = weights
+        .iter()
+        .filter(|(folder, weight)| {
+            let valid = weight.is_finite() && **weight >= 0.0;
+            if !valid {
+                warn!(
+                    "Ignoring weight of {}: {weight} is not a valid weight",
+                    folder.to_string_lossy()
+                );
+            }
+            valid
+        })
+        .map(|(folder, &weight)| (folder.as_path(), weight))
+        .collect();
+
+    let mut scan = Scan::default();
+    let mut visited = HashSet::new();
+
+    for source in sources {
+        let paths = if is_pattern(source) {
+            // New matches appear in directory of the pattern
+            // or next to the files matched already
+            let base = source
+                .ancestors()
+                .find(|dir| !is_pattern(dir))
+                .unwrap_or(Path::new("."));
+            scan.watch(base);
+
+            let paths = expand(source);
+            if paths.is_empty() {
+                warn!("No files match {}", source.to_string_lossy());
+            }
+            for dir in paths.iter().filter_map(|path| path.parent()) {
+                scan.watch(dir);
+            }
+            paths
+        } else {
+            vec![source.clone()]
+        };
+
+        for path in paths {
+            if path.is_dir() {
+                scan_dir(&path, &path, &weights, &mut visited, &mut scan);
+            } else if path.exists() {
+                scan.candidates.extend(Candidate::new(path));
+            } else {
+                warn!("{} does not exist", path.to_string_lossy());
+            }
+        }
+    }
+
+    scan.candidates = resolve_variants(std::mem::take(&mut scan.candidates), target);
+    info!("Found {} available backgrounds", scan.candidates.len());
+
+    scan
+}
+
+#[cfg(test)]
+impl Candidate {
+    /// Image that is never read from disk
+    pub fn fake(path: &str, size: Option<(i32, i32)>) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            content_type: "image/png".into(),
+            media: Media::Image,
+            weight: 1.0,
+            size,
+            stamp: None,
+        }
+    }
+}
+
+/// Supported files of background sources, see [`scan`]
+#[derive(Default)]
+pub struct Scan {
+    pub candidates: Vec<Candidate>,
+    /// Directories looked into and time
+    /// they were modified at before that
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Scan {
+    /// Whether files could have been added, removed or renamed
+    /// since the scan. Files changed in place are not noticed
+    pub fn is_outdated(&self) -> bool {
+        self.dirs
+            .iter()
+            .any(|(dir, modified)| modification_time(dir) != *modified)
+    }
+
+    fn watch(&mut self, dir: &Path) {
+        if !self.dirs.iter().any(|(watched, _)| watched == dir) {
+            self.dirs.push((dir.to_owned(), modification_time(dir)));
+        }
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn scan_dir(
+    root: &Path,
+    dir: &Path,
+    weights: &HashMap<&Path, f64>,
+    visited: &mut HashSet<PathBuf>,
+    scan: &mut Scan,
+) {
+    // Symlinks may form a loop
+    let Ok(canonical) = fs::canonicalize(dir) else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+    // Taken before reading, so that files added
+    // meanwhile are found by the next scan
+    scan.watch(dir);
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        warn!("Failed to read directory {}", dir.to_string_lossy());
+        return;
+    };
+
+    let mut children: Vec<PathBuf> = entries
+        .flat_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().as_bytes().starts_with(b"."))
+        .map(|entry| entry.path())
+        .collect();
+    children.sort();
+
+    for path in children {
+        if path.is_dir() {
+            scan_dir(root, &path, weights, visited, scan);
+        } else if let Some(mut candidate) = Candidate::new(path) {
+            candidate.weight = folder_weight(root, &candidate.path, weights);
+            scan.candidates.push(candidate);
+        }
+    }
+}
+
+fn folder_weight(root: &Path, path: &Path, weights: &HashMap<&Path, f64>) -> f64 {
+    let Some(folder) = path.strip_prefix(root).ok().and_then(Path::parent) else {
+        return 1.0;
+    };
+
+    folder
+        .ancestors()
+        .find_map(|folder| weights.get(folder))
+        .copied()
+        .unwrap_or(1.0)
+}
+
+/// Weighted random choice among files fitting `target`
+/// best, avoiding recently shown files
+///
+/// Up to half of candidates shown most recently are excluded,
+/// so that the same file does not come up twice in a row
+pub fn pick<'a>(candidates: &[&'a Candidate], target: Option<Target>) -> Option<&'a Candidate> {
+    let candidates = best_fitting(candidates, target);
+    // Held until picked file is recorded, so that other
+    // monitors see it as recently shown
+    let _history = HISTORY_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut history = read_history();
+
+    // Weights of recently shown files may be the only non-zero ones
+    let picked =
+        choose_weighted(&fresh(&candidates, &history)).or_else(|| choose_weighted(&candidates));
+    let Some(picked) = picked else {
+        if !candidates.is_empty() {
+            warn!("All available backgrounds have zero weight");
+        }
+        return None;
+    };
+
+    history.push(picked.path.clone());
+    write_history(&history);
+
+    Some(picked)
+}
+
+/// `candidates` except up to half of them that
+/// were shown most recently according to `history`
+fn fresh<'a>(candidates: &[&'a Candidate], history: &[PathBuf]) -> Vec<&'a Candidate> {
+    let avoid = (candidates.len() / 2).min(HISTORY_LENGTH);
+    let recent: Vec<&PathBuf> = history
+        .iter()
+        .rev()
+        .filter(|path| candidates.iter().any(|candidate| &candidate.path == *path))
+        .unique()
+        .take(avoid)
+        .collect();
+
+    candidates
+        .iter()
+        .copied()
+        .filter(|candidate| !recent.contains(&&candidate.path))
+        .collect()
+}
+
+fn choose_weighted<'a>(candidates: &[&'a Candidate]) -> Option<&'a Candidate> {
+    candidates
+        .choose_weighted(&mut rand::rng(), |candidate| candidate.weight)
+        .ok()
+        .copied()
+}
+
+pub async fn load_background_paintable(
+    sources: Vec<PathBuf>,
+    weights: HashMap<PathBuf, f64>,
+    target: Option<Target>,
+    scale_to: Option<Target>,
+) -> Option<gdk::Paintable> {
+    // Scanning directories may be slow as well, e.g. on network home
+    let candidate = gio::spawn_blocking(move || select(&sources, &weights, target))
+        .await
+        .ok()
+        .flatten()?;
+
+    candidate.load(scale_to).await
+}
+
+fn select(
+    sources: &[PathBuf],
+    weights: &HashMap<PathBuf, f64>,
+    target: Option<Target>,
+) -> Option<Candidate> {
+    if sources.is_empty() {
+        return None;
+    }
+
+    if is_single_file(sources) {
+        let candidate = Candidate::new(sources[0].clone());
+        if candidate.is_none() {
+            warn!(
+                "{} is not a readable image or video",
+                sources[0].to_string_lossy()
+            );
+        }
+        return candidate;
+    }
+
+    let candidates = scan(sources, weights, target).candidates;
+    pick(&candidates.iter().collect::<Vec<_>>(), target).cloned()
+}
+
+fn history_file() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("background-history"))
+}
+
+/// Previously shown backgrounds, most recent last
+fn read_history() -> Vec<PathBuf> {
+    let Some(data) = history_file().and_then(|file| fs::read(file).ok()) else {
+        return Vec::new();
+    };
+
+    data.split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+        .collect()
+}
+
+fn write_history(history: &[PathBuf]) {
+    let Some(file) = history_file() else {
+        return;
+    };
+
+    let mut data = Vec::new();
+    for path in &history[history.len().saturating_sub(HISTORY_LENGTH)..] {
+        data.extend_from_slice(path.as_os_str().as_bytes());
+        data.push(b'\n');
+    }
+
+    let result = file
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&file, data));
+    if let Err(err) = result {
+        warn!(
+            "Failed to save background history to {}: {err}",
+            file.to_string_lossy()
+        );
+    }
+}
+
+/// Detect content type by file contents, using
+/// name only as a hint
+fn content_type(path: &Path) -> Option<glib::GString> {
+    let mut data = Vec::new();
+    if let Err(err) =
+        File::open(path).and_then(|file| file.take(SNIFF_LENGTH).read_to_end(&mut data))
+    {
+        warn!("Failed to read {}: {err}", path.to_string_lossy());
+        return None;
+    }
+
+    let (content_type, _) = gio::content_type_guess(Some(path), &data[..]);
+    Some(content_type)
+}
+
+/// Anything GTK or GStreamer can possibly decode is accepted,
+/// actual decoding errors are reported when loading
+fn media(content_type: &str) -> Option<Media> {
+    let mime = gio::content_type_get_mime_type(content_type)?;
+
+    if mime.starts_with("image/") {
+        Some(Media::Image)
+    } else if mime.starts_with("video/") {
+        Some(Media::Video)
+    } else {
+        None
+    }
+}
+
+async fn load_image(candidate: &Candidate, scale_to: Option<Target>) -> Option<gdk::Paintable> {
+    let src = &candidate.path;
+    let animated = gio::content_type_get_mime_type(&candidate.content_type)
+        .is_some_and(|mime| ANIMATED_TYPES.contains(&mime.as_str()));
+
+    if animated {
+        let animation = match open(src).await {
+            Ok(stream) => PixbufAnimation::from_stream_future(&stream).await,
+            Err(err) => Err(err),
+        };
+
+        match animation {
+            Ok(animation) if !animation.is_static_image() => {
+                return Some(AnimatedImage::new(&animation).upcast());
+            }
+            Ok(_) => (),
+            // Still image loader may still be able to handle it
+            Err(err) => warn!(
+                "Failed to load {} as animation: {err}",
+                src.to_string_lossy()
+            ),
+        }
+    }
+
+    let size = scale_to
+        .zip(candidate.size)
+        .and_then(|(target, size)| target.downscaled_size(size));
+
+    texture(src, candidate.stamp, size)
+        .await
+        .map(|texture| texture.upcast())
+}
+
+async fn open(src: &Path) -> Result<gio::FileInputStream, glib::Error> {
+    gio::File::for_path(src)
+        .read_future(glib::Priority::DEFAULT)
+        .await
+}
+
+/// Decoded no larger than needed to cover `scale_to`
+/// and `[background.video] max_size`
+fn load_video(src: &Path, scale_to: Option<Target>) -> Option<gdk::Paintable> {
+    let max_size = [
+        scale_to.map(|target| target.longest_side()),
+        config().background.video.max_size,
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+
+    let name = src.to_string_lossy().into_owned();
+    let bg_video = match VideoStream::new(&gio::File::for_path(src).uri(), max_size) {
+        Ok(video) => video,
+        Err(err) => {
+            warn!("Failed to play {name}: {err}");
+            return None;
+        }
+    };
+
+    bg_video.connect_error_notify(move |video| {
+        if let Some(err) = video.error() {
+            warn!("Failed to play {name}: {err}");
+        }
+    });
+
+    // Playback is started by video::manage
+    bg_video.set_loop(true);
+    Some(bg_video.upcast())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn deepest_weighted_folder_wins() {
+        let weights = HashMap::from([
+            (Path::new("nature"), 2.0),
+            (Path::new("nature/forest"), 0.5),
+        ]);
+        let weight = |path| folder_weight(Path::new("/bg"), Path::new(path), &weights);
+
+        assert_eq!(weight("/bg/a.jpg"), 1.0);
+        assert_eq!(weight("/bg/city/a.jpg"), 1.0);
+        assert_eq!(weight("/bg/nature/a.jpg"), 2.0);
+        assert_eq!(weight("/bg/nature/sea/a.jpg"), 2.0);
+        assert_eq!(weight("/bg/nature/forest/a.jpg"), 0.5);
+        assert_eq!(weight("/bg/nature/forest/pine/a.jpg"), 0.5);
+    }
+
+    #[test]
+    fn recent_half_is_avoided() {
+        let candidates: Vec<Candidate> = ["a", "b", "c", "d", "e", "f"]
+            .into_iter()
+            .map(|path| Candidate::fake(path, None))
+            .collect();
+        let candidates: Vec<&Candidate> = candidates.iter().collect();
+        // Most recent last, files no longer available do not count
+        let history: Vec<PathBuf> = ["a", "b", "c", "gone", "d", "c", "e", "gone"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+
+        let fresh: Vec<&str> = fresh(&candidates, &history)
+            .iter()
+            .map(|candidate| candidate.path.to_str().unwrap())
+            .collect();
+        assert_eq!(fresh, ["a", "b", "f"]);
+    }
+
+    #[test]
+    fn avoided_files_are_capped_by_history_length() {
+        let candidates: Vec<Candidate> = (0..200)
+            .map(|index| Candidate::fake(&index.to_string(), None))
+            .collect();
+        let candidates: Vec<&Candidate> = candidates.iter().collect();
+        let history: Vec<PathBuf> = candidates
+            .iter()
+            .map(|candidate| candidate.path.clone())
+            .collect();
+
+        assert_eq!(
+            fresh(&candidates, &history).len(),
+            candidates.len() - HISTORY_LENGTH
+        );
+        assert_eq!(fresh(&candidates[..1], &history).len(), 1);
+    }
+
+    #[test]
+    fn symlink_loops_are_scanned_once() {
+        let root = std::env::temp_dir().join(format!("shackle-scan-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        symlink(&root, root.join("sub/loop")).unwrap();
+
+        let mut visited = HashSet::new();
+        let mut scan = Scan::default();
+        scan_dir(&root, &root, &HashMap::new(), &mut visited, &mut scan);
+
+        assert_eq!(visited.len(), 2);
+        assert_eq!(scan.dirs.len(), 2);
+        assert!(!scan.is_outdated());
+
+        fs::remove_file(root.join("sub/loop")).unwrap();
+        fs::remove_dir(root.join("sub")).unwrap();
+        assert!(scan.is_outdated());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use gtk::gdk;
+use gtk::prelude::*;
+
+use super::load::Candidate;
+
+/// Images with aspect ratio differing from monitor by
+/// no more than this fraction are considered fitting
+const ASPECT_TOLERANCE: f64 = 0.1;
+
+/// Monitor background is picked for
+#[derive(Clone, Copy)]
+pub struct Target {
+    /// Size in physical pixels
+    width: f64,
+    height: f64,
+    scale: f64,
+}
+
+impl Target {
+    /// Returns [`None`] if monitor does not report its size yet
+    pub fn for_monitor(monitor: &gdk::Monitor) -> Option<Self> {
+        let geometry = monitor.geometry();
+        let scale = monitor.scale();
+
+        let target = Self {
+            width: geometry.width() as f64 * scale,
+            height: geometry.height() as f64 * scale,
+            scale,
+        };
+
+        (target.width > 0.0 && target.height > 0.0).then_some(target)
+    }
+
+    /// Longer side of monitor in physical pixels. Anything fitting
+    /// into a square of this size covers monitor in any orientation
+    pub fn longest_side(&self) -> u32 {
+        self.width.max(self.height).ceil() as u32
+    }
+
+    /// Size image of `size` may be downscaled to while still
+    /// covering the monitor, or [`None`] if it is small already
+    pub fn downscaled_size(&self, (width, height): (i32, i32)) -> Option<(i32, i32)> {
+        let (width_f, height_f) = (width as f64, height as f64);
+        // Size is known before EXIF orientation is applied, so
+        // image must be large enough when rotated as well
+        let factor = (self.width / width_f)
+            .max(self.height / height_f)
+            .max(self.width / height_f)
+            .max(self.height / width_f);
+
+        (factor < 1.0).then(|| {
+            (
+                (width_f * factor).ceil() as i32,
+                (height_f * factor).ceil() as i32,
+            )
+        })
+    }
+
+    fn aspect_matches(&self, candidate: &Candidate) -> bool {
+        let Some((width, height)) = candidate.size() else {
+            return true;
+        };
+
+        let ratio = (width as f64 / height as f64) / (self.width / self.height);
+        ratio.ln().abs() <= (1.0 + ASPECT_TOLERANCE).ln()
+    }
+
+    fn covered_by(&self, candidate: &Candidate) -> bool {
+        self.coverage(candidate)
+            .is_none_or(|coverage| coverage >= 1.0)
+    }
+
+    /// How many times `candidate` is larger than needed to
+    /// cover the monitor without upscaling
+    fn coverage(&self, candidate: &Candidate) -> Option<f64> {
+        candidate
+            .size()
+            .map(|(width, height)| (width as f64 / self.width).min(height as f64 / self.height))
+    }
+}
+
+/// Candidates fitting `target` best
+///
+/// Files matching aspect ratio of the monitor and large enough to
+/// not be upscaled are preferred, then files matching aspect ratio
+/// only. If there are none all candidates are returned. Files of
+/// unknown size, such as videos, are assumed to fit
+pub fn best_fitting<'a>(
+    candidates: &[&'a Candidate],
+    target: Option<Target>,
+) -> Vec<&'a Candidate> {
+    let Some(target) = target else {
+        return candidates.to_vec();
+    };
+
+    let aspect: Vec<&Candidate> = candidates
+        .iter()
+        .copied()
+        .filter(|candidate| target.aspect_matches(candidate))
+        .collect();
+    let covering: Vec<&Candidate> = aspect
+        .iter()
+        .copied()
+        .filter(|candidate| target.covered_by(candidate))
+        .collect();
+
+    if !covering.is_empty() {
+        covering
+    } else if !aspect.is_empty() {
+        aspect
+    } else {
+        candidates.to_vec()
+    }
+}
+
+/// Replace resolution variants of the same image, e.g. `name.jpg`
+/// and `name@2x.jpg`, with the one best suited for `target`
+pub fn resolve_variants(candidates: Vec<Candidate>, target: Option<Target>) -> Vec<Candidate> {
+    let mut groups: HashMap<(PathBuf, String), Vec<(f64, Candidate)>> = HashMap::new();
+    let mut order = Vec::new();
+
+    for candidate in candidates {
+        let (name, scale) = variant(&candidate.path);
+        let key = (
+            candidate.path.parent().unwrap_or(Path::new("")).to_owned(),
+            name,
+        );
+
+        let group = groups.entry(key.clone()).or_default();
+        if group.is_empty() {
+            order.push(key);
+        }
+        group.push((scale, candidate));
+    }
+
+    order
+        .into_iter()
+        .filter_map(|key| groups.remove(&key))
+        .filter_map(|variants| best_variant(variants, target))
+        .collect()
+}
+
+/// Smallest variant that does not need upscaling or the largest one
+fn best_variant(variants: Vec<(f64, Candidate)>, target: Option<Target>) -> Option<Candidate> {
+    let Some(target) = target else {
+        // Without monitor information base variant is the safest guess
+        return variants
+            .into_iter()
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, candidate)| candidate);
+    };
+
+    // Actual size is more reliable than name, scale
+    // is only used for files of unknown size
+    let coverage = |(scale, candidate): &(f64, Candidate)| {
+        target.coverage(candidate).unwrap_or(scale / target.scale)
+    };
+
+    let (sufficient, insufficient): (Vec<_>, Vec<_>) = variants
+        .into_iter()
+        .partition(|variant| coverage(variant) >= 1.0);
+
+    let best = if sufficient.is_empty() {
+        insufficient
+            .into_iter()
+            .max_by(|a, b| coverage(a).total_cmp(&coverage(b)))
+    } else {
+        sufficient
+            .into_iter()
+            .min_by(|a, b| coverage(a).total_cmp(&coverage(b)))
+    };
+
+    best.map(|(_, candidate)| candidate)
+}
+
+/// Name of image without resolution suffix and its scale,
+/// `"name@2x.jpg"` is `("name", 2.0)`. Extension is not
+/// considered part of the name so that variants may differ in format
+fn variant(path: &Path) -> (String, f64) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let scale = stem
+        .rsplit_once('@')
+        .and_then(|(name, suffix)| Some((name, suffix.strip_suffix('x')?.parse::<f64>().ok()?)))
+        .filter(|(name, scale)| !name.is_empty() && scale.is_finite() && *scale > 0.0);
+
+    match scale {
+        Some((name, scale)) => (name.to_owned(), scale),
+        None => (stem, 1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL_HD: Target = Target {
+        width: 1920.0,
+        height: 1080.0,
+        scale: 1.0,
+    };
+    /// Same monitor at scale of 2
+    const HIDPI: Target = Target {
+        width: 3840.0,
+        height: 2160.0,
+        scale: 2.0,
+    };
+
+    fn paths(candidates: &[&Candidate]) -> Vec<String> {
+        candidates
+            .iter()
+            .map(|candidate| candidate.path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn resolved(candidates: Vec<Candidate>, target: Option<Target>) -> Vec<String> {
+        paths(
+            &resolve_variants(candidates, target)
+                .iter()
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn aspect_mismatch_is_avoided() {
+        let candidates = [
+            Candidate::fake("wide.jpg", Some((3840, 2160))),
+            Candidate::fake("square.jpg", Some((2000, 2000))),
+            Candidate::fake("slightly-wider.jpg", Some((2000, 1080))),
+            Candidate::fake("video.mp4", None),
+        ];
+        let candidates: Vec<&Candidate> = candidates.iter().collect();
+
+        assert_eq!(
+            paths(&best_fitting(&candidates, Some(FULL_HD))),
+            ["wide.jpg", "slightly-wider.jpg", "video.mp4"]
+        );
+        assert_eq!(paths(&best_fitting(&candidates, None)).len(), 4);
+        // Mismatching files are still used if there is nothing else
+        assert_eq!(
+            paths(&best_fitting(&candidates[1..2], Some(FULL_HD))),
+            ["square.jpg"]
+        );
+    }
+
+    #[test]
+    fn undersized_images_are_avoided() {
+        let candidates = [
+            Candidate::fake("small.jpg", Some((1280, 720))),
+            Candidate::fake("large.jpg", Some((2560, 1440))),
+            Candidate::fake("square.jpg", Some((4000, 4000))),
+        ];
+        let candidates: Vec<&Candidate> = candidates.iter().collect();
+
+        assert_eq!(
+            paths(&best_fitting(&candidates, Some(FULL_HD))),
+            ["large.jpg"]
+        );
+        assert_eq!(
+            paths(&best_fitting(&candidates, Some(HIDPI))),
+            ["small.jpg", "large.jpg"]
+        );
+    }
+
+    #[test]
+    fn variant_matching_scale_is_kept() {
+        let images = || {
+            vec![
+                Candidate::fake("forest.jpg", Some((1920, 1080))),
+                Candidate::fake("forest@2x.jpg", Some((3840, 2160))),
+                Candidate::fake("sea.png", Some((1920, 1080))),
+            ]
+        };
+
+        assert_eq!(resolved(images(), Some(FULL_HD)), ["forest.jpg", "sea.png"]);
+        assert_eq!(
+            resolved(images(), Some(HIDPI)),
+            ["forest@2x.jpg", "sea.png"]
+        );
+        assert_eq!(resolved(images(), None), ["forest.jpg", "sea.png"]);
+    }
+
+    #[test]
+    fn variants_of_unknown_size_go_by_name() {
+        let videos = || {
+            vec![
+                Candidate::fake("waves@2x.mp4", None),
+                Candidate::fake("waves.mp4", None),
+            ]
+        };
+
+        assert_eq!(resolved(videos(), Some(FULL_HD)), ["waves.mp4"]);
+        assert_eq!(resolved(videos(), Some(HIDPI)), ["waves@2x.mp4"]);
+    }
+
+    #[test]
+    fn variant_names_are_parsed() {
+        assert_eq!(
+            variant(Path::new("a/name@2x.jpg")),
+            ("name".to_owned(), 2.0)
+        );
+        assert_eq!(
+            variant(Path::new("name@1.5x.png")),
+            ("name".to_owned(), 1.5)
+        );
+        assert_eq!(variant(Path::new("name.jpg")), ("name".to_owned(), 1.0));
+        assert_eq!(variant(Path::new("@2x.jpg")), ("@2x".to_owned(), 1.0));
+        assert_eq!(variant(Path::new("name@x.jpg")), ("name@x".to_owned(), 1.0));
+        assert_eq!(
+            variant(Path::new("name@0x.jpg")),
+            ("name@0x".to_owned(), 1.0)
+        );
+    }
+}
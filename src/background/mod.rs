@@ -0,0 +1,420 @@
+mod animation;
+mod cache;
+mod fill;
+mod glob;
+mod load;
+mod matching;
+mod palette;
+mod picture;
+mod schedule;
+mod screenshot;
+mod slideshow;
+mod stream;
+mod video;
+
+use std::path::PathBuf;
+
+use gtk::gdk;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use log::{info, warn};
+
+use crate::config::{config, BackgroundConfig, BackgroundFit, EffectsConfig, ScreenshotConfig};
+use crate::style::set_background_palette;
+
+use self::fill::Fill;
+use self::load::load_background_paintable;
+use self::matching::Target;
+use self::picture::{BackgroundPicture, Effects};
+use self::schedule::{Period, Schedule};
+use self::slideshow::Slideshow;
+
+pub use self::palette::Palette;
+pub use self::screenshot::capture_screenshots;
+
+/// Duration of fade in of background once it is loaded, in milliseconds
+const FADE_IN_DURATION: u32 = 300;
+/// Seconds between checks whether day turned into night or back.
+/// Timers are not reliable across suspend, so time is polled
+const SCHEDULE_CHECK_INTERVAL: u32 = 60;
+/// Screenshot has to be either pixelated by blocks of at least
+/// this size or blurred by at least this radius, so that lock
+/// screen does not show readable contents of the session
+const MIN_SCREENSHOT_PIXELATE: u32 = 4;
+const MIN_SCREENSHOT_BLUR: f64 = 5.0;
+
+/// How backgrounds are presented, shared by all
+/// backgrounds of a monitor
+#[derive(Clone)]
+struct Appearance {
+    fit: BackgroundFit,
+    fill: Option<Fill>,
+    effects: Effects,
+}
+
+impl Appearance {
+    /// Monitor images may be downscaled for without losing
+    /// detail. Fits showing images in their own size need
+    /// them unchanged
+    fn scale_to(&self, target: Option<Target>) -> Option<Target> {
+        match self.fit {
+            BackgroundFit::Cover | BackgroundFit::Contain | BackgroundFit::Fill => target,
+            BackgroundFit::ScaleDown | BackgroundFit::Centered | BackgroundFit::Tiled => None,
+        }
+    }
+}
+
+pub fn background(monitor: &gdk::Monitor) -> gtk::Widget {
+    let config = config();
+    let Some(screenshot) = &config.background.screenshot else {
+        return configured(&config.background, monitor).upcast();
+    };
+
+    let appearance = Appearance {
+        // Screenshot has exactly the size of monitor
+        fit: BackgroundFit::Fill,
+        fill: None,
+        effects: screenshot_effects(screenshot),
+    };
+
+    // Lock is shown right away, screenshot fades in once captured
+    let stack = crossfade_stack();
+    show(&stack, &placeholder_picture(&appearance));
+    let stack_ref = stack.downgrade();
+    let monitor = monitor.clone();
+
+    glib::spawn_future_local(async move {
+        let texture = screenshot::screenshot(&monitor).await;
+        let Some(stack) = stack_ref.upgrade() else {
+            return;
+        };
+
+        match texture {
+            Some(texture) => {
+                show(&stack, &picture(Some(texture.upcast_ref()), &appearance));
+                set_background_palette(&monitor, palette::extract(&texture).await);
+            }
+            None => show(
+                &stack,
+                configured(&config.background, &monitor).upcast_ref(),
+            ),
+        }
+    });
+
+    stack.upcast()
+}
+
+/// Background of `monitor` configured by `config`
+fn configured(config: &BackgroundConfig, monitor: &gdk::Monitor) -> gtk::Stack {
+    let schedule = config.schedule.as_ref().map(Schedule::new);
+    let period = schedule.as_ref().map(Schedule::period);
+
+    let container = crossfade_stack();
+    show(
+        &container,
+        content(config, monitor, period, true).upcast_ref(),
+    );
+
+    if let Some((schedule, period)) = schedule.zip(period) {
+        follow_schedule(&container, monitor, schedule, period);
+    }
+
+    container
+}
+
+/// Background of `monitor` for `period` of day
+///
+/// Unless `placeholder` is set nothing is shown until
+/// background is loaded
+fn content(
+    config: &BackgroundConfig,
+    monitor: &gdk::Monitor,
+    period: Option<Period>,
+    placeholder: bool,
+) -> gtk::Stack {
+    let sources = background_sources(config, monitor, period);
+    let target = Target::for_monitor(monitor);
+    let appearance = Appearance {
+        fit: config.fit,
+        fill: Fill::from_config(config),
+        effects: background_effects(&config.effects),
+    };
+
+    let stack = crossfade_stack();
+    follow_colors(&stack, monitor);
+    if placeholder {
+        // Lock surface is shown right away, background
+        // fades in once it is loaded
+        show(&stack, &placeholder_picture(&appearance));
+    }
+
+    match &config.slideshow {
+        Some(slideshow) if !sources.is_empty() => Slideshow::start(
+            &stack,
+            sources,
+            &config.weights,
+            target,
+            appearance,
+            slideshow,
+        ),
+        _ => {
+            let stack_ref = stack.downgrade();
+            let sources = sources.to_owned();
+            let weights = config.weights.clone();
+            let scale_to = appearance.scale_to(target);
+
+            glib::spawn_future_local(async move {
+                let bg_paintable =
+                    load_background_paintable(sources, weights, target, scale_to).await;
+
+                if let Some((stack, bg_paintable)) = stack_ref.upgrade().zip(bg_paintable) {
+                    show(&stack, &picture(Some(&bg_paintable), &appearance));
+                }
+            });
+        }
+    }
+
+    stack
+}
+
+/// Switch backgrounds of `container` when day turns into night and back
+fn follow_schedule(
+    container: &gtk::Stack,
+    monitor: &gdk::Monitor,
+    schedule: Schedule,
+    mut period: Period,
+) {
+    let container = container.downgrade();
+    let monitor = monitor.clone();
+
+    glib::timeout_add_seconds_local(SCHEDULE_CHECK_INTERVAL, move || {
+        let Some(container) = container.upgrade() else {
+            return glib::ControlFlow::Break;
+        };
+
+        let current = schedule.period();
+        if current == period {
+            return glib::ControlFlow::Continue;
+        }
+        let previous = std::mem::replace(&mut period, current);
+
+        let config = config();
+        let sources = background_sources(&config.background, &monitor, Some(current));
+        if sources == background_sources(&config.background, &monitor, Some(previous)) {
+            // Monitor has its own background regardless of time
+            return glib::ControlFlow::Continue;
+        }
+
+        info!("Switching to {current:?} backgrounds");
+
+        // Background of the previous switch may be still loading
+        remove_hidden(&container);
+
+        // Current background is kept until the new one is loaded
+        let next = content(&config.background, &monitor, Some(current), false);
+        container.add_child(&next);
+        next.connect_visible_child_notify(clone!(
+            #[weak]
+            container,
+            move |next| {
+                let next = next.upcast_ref::<gtk::Widget>();
+                let pending = next.parent().as_ref() == Some(container.upcast_ref())
+                    && container.visible_child().as_ref() != Some(next);
+                if pending {
+                    reveal(&container, next);
+                }
+            }
+        ));
+
+        glib::ControlFlow::Continue
+    });
+}
+
+/// Keep colors of theme in line with background shown in `stack`
+fn follow_colors(stack: &gtk::Stack, monitor: &gdk::Monitor) {
+    stack.connect_visible_child_notify(clone!(
+        #[strong]
+        monitor,
+        move |stack| {
+            let Some(picture) = stack.visible_child().and_downcast::<BackgroundPicture>() else {
+                return;
+            };
+
+            glib::spawn_future_local(clone!(
+                #[weak]
+                stack,
+                #[strong]
+                monitor,
+                async move {
+                    // Colors are not taken from videos and animations
+                    let palette = match picture.paintable().and_downcast::<gdk::Texture>() {
+                        Some(texture) => palette::extract(&texture).await,
+                        None => None,
+                    };
+
+                    // Another background may have been shown meanwhile
+                    if stack.visible_child().as_ref() == Some(picture.upcast_ref()) {
+                        set_background_palette(&monitor, palette);
+                    }
+                }
+            ));
+        }
+    ));
+}
+
+fn crossfade_stack() -> gtk::Stack {
+    let stack = gtk::Stack::new();
+    stack.set_transition_type(gtk::StackTransitionType::Crossfade);
+    stack.set_transition_duration(FADE_IN_DURATION);
+    stack.connect_transition_running_notify(|stack| {
+        if !stack.is_transition_running() {
+            remove_hidden(stack);
+        }
+    });
+
+    stack
+}
+
+/// Switch to `child` with crossfade
+fn show(stack: &gtk::Stack, child: &gtk::Widget) {
+    stack.add_child(child);
+    reveal(stack, child);
+}
+
+/// Switch to `child` already added to `stack`
+fn reveal(stack: &gtk::Stack, child: &gtk::Widget) {
+    stack.set_visible_child(child);
+    if !stack.is_transition_running() {
+        remove_hidden(stack);
+    }
+}
+
+/// Drop backgrounds that are no longer visible
+fn remove_hidden(stack: &gtk::Stack) {
+    let visible = stack.visible_child();
+
+    let mut child = stack.first_child();
+    while let Some(widget) = child {
+        child = widget.next_sibling();
+        if Some(&widget) != visible.as_ref() {
+            stack.remove(&widget);
+        }
+    }
+}
+
+/// Configured fill or black if there is none
+fn placeholder_picture(appearance: &Appearance) -> gtk::Widget {
+    let fill = appearance
+        .fill
+        .clone()
+        .unwrap_or(Fill::Color(gdk::RGBA::BLACK));
+
+    BackgroundPicture::new(None, appearance.fit, Some(fill), appearance.effects).upcast()
+}
+
+fn picture(paintable: Option<&gdk::Paintable>, appearance: &Appearance) -> gtk::Widget {
+    let picture: gtk::Widget = BackgroundPicture::new(
+        paintable,
+        appearance.fit,
+        appearance.fill.clone(),
+        appearance.effects,
+    )
+    .upcast();
+
+    let video = paintable.and_then(|paintable| paintable.downcast_ref::<gtk::MediaStream>());
+    if let Some(video) = video {
+        video::manage(&picture, video);
+    }
+
+    picture
+}
+
+fn background_effects(config: &EffectsConfig) -> Effects {
+    let tint = config
+        .tint
+        .as_ref()
+        .and_then(|tint| match gdk::RGBA::parse(tint.as_str()) {
+            Ok(tint) => Some(tint),
+            Err(_) => {
+                warn!("Ignoring background.effects.tint: \"{tint}\" is not a valid color");
+                None
+            }
+        });
+
+    Effects {
+        blur: length("background.effects.blur", config.blur),
+        dim: fraction("background.effects.dim", config.dim),
+        grayscale: fraction("background.effects.grayscale", config.grayscale),
+        vignette: fraction("background.effects.vignette", config.vignette),
+        tint,
+    }
+}
+
+fn screenshot_effects(config: &ScreenshotConfig) -> Effects {
+    let mut blur = length("background.screenshot.blur", config.blur);
+    if config.pixelate < MIN_SCREENSHOT_PIXELATE && blur < MIN_SCREENSHOT_BLUR {
+        warn!("Ignoring background.screenshot.blur: {blur} leaves text readable");
+        blur = MIN_SCREENSHOT_BLUR;
+    }
+
+    Effects {
+        blur,
+        dim: fraction("background.screenshot.dim", config.dim),
+        ..Effects::default()
+    }
+}
+
+/// `value` if it is a valid length in pixels, 0 otherwise
+fn length(key: &str, value: f64) -> f64 {
+    if value.is_finite() && value >= 0.0 {
+        value
+    } else {
+        warn!("Ignoring {key}: {value} is not a valid length");
+        0.0
+    }
+}
+
+/// `value` if it is between 0 and 1, 0 otherwise
+fn fraction(key: &str, value: f64) -> f64 {
+    if (0.0..=1.0).contains(&value) {
+        value
+    } else {
+        warn!("Ignoring {key}: {value} is not between 0 and 1");
+        0.0
+    }
+}
+
+/// Backgrounds of the first `[[background.output]]` rule
+/// matching `monitor`, or global ones if none match. Global
+/// backgrounds depend on `period` if schedule is configured,
+/// falling back to `path` if there are none for the period
+fn background_sources<'a>(
+    config: &'a BackgroundConfig,
+    monitor: &gdk::Monitor,
+    period: Option<Period>,
+) -> &'a [PathBuf] {
+    let matches = |expected: &Option<String>, actual: Option<glib::GString>| {
+        expected
+            .as_ref()
+            .is_none_or(|expected| actual.as_deref() == Some(expected.as_str()))
+    };
+
+    config
+        .outputs
+        .iter()
+        .find(|output| {
+            matches(&output.connector, monitor.connector())
+                && matches(&output.manufacturer, monitor.manufacturer())
+                && matches(&output.model, monitor.model())
+        })
+        .map_or_else(
+            || match (&config.schedule, period) {
+                (Some(schedule), Some(Period::Day)) if !schedule.day.is_empty() => &schedule.day,
+                (Some(schedule), Some(Period::Night)) if !schedule.night.is_empty() => {
+                    &schedule.night
+                }
+                _ => &config.paths,
+            },
+            |output| &output.paths,
+        )
+}
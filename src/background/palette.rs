@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use gtk::gdk;
+use gtk::gio;
+use gtk::prelude::*;
+
+/// Pixels are sampled on a grid of this many
+/// columns and rows, whatever the image size
+const SAMPLE_GRID: usize = 64;
+/// Bits of each channel colors are grouped by
+const QUANTIZE_BITS: u32 = 4;
+/// Colors less saturated than this are not considered accents
+const MIN_ACCENT_SATURATION: f64 = 0.3;
+/// Colors darker than this are not considered accents
+const MIN_ACCENT_VALUE: f64 = 0.2;
+
+/// Colors of background image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette {
+    /// Most common color
+    pub dominant: gdk::RGBA,
+    /// Most prominent vivid color, same as dominant
+    /// if image has none
+    pub accent: gdk::RGBA,
+}
+
+/// Pixels with similar colors
+#[derive(Default)]
+struct Group {
+    count: u32,
+    sum: [u32; 3],
+}
+
+impl Group {
+    fn mean(&self) -> [f64; 3] {
+        self.sum
+            .map(|channel| channel as f64 / self.count as f64 / 255.0)
+    }
+}
+
+/// Image is downloaded and colors are counted in a GIO worker
+/// thread, so large images do not block main thread
+pub async fn extract(texture: &gdk::Texture) -> Option<Palette> {
+    let texture = texture.clone();
+    gio::spawn_blocking(move || {
+        let mut downloader = gdk::TextureDownloader::new(&texture);
+        downloader.set_format(gdk::MemoryFormat::R8g8b8a8);
+        let (data, stride) = downloader.download_bytes();
+
+        let width = texture.width() as usize;
+        let height = texture.height() as usize;
+        analyze(&data, width, height, stride)
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// `data` is in `R8g8b8a8` format
+fn analyze(data: &[u8], width: usize, height: usize, stride: usize) -> Option<Palette> {
+    let mut groups: HashMap<u32, Group> = HashMap::new();
+
+    let rows = SAMPLE_GRID.min(height);
+    let columns = SAMPLE_GRID.min(width);
+
+    for row in 0..rows {
+        let y = row * height / rows;
+        for column in 0..columns {
+            let offset = y * stride + column * width / columns * 4;
+            let Some(&[r, g, b, a]) = data.get(offset..offset + 4) else {
+                continue;
+            };
+            // Transparent parts show fill instead
+            if a < 128 {
+                continue;
+            }
+
+            let key = [r, g, b].iter().fold(0, |key, &channel| {
+                (key << QUANTIZE_BITS) | (channel as u32 >> (8 - QUANTIZE_BITS))
+            });
+            let group = groups.entry(key).or_default();
+            group.count += 1;
+            for (sum, channel) in group.sum.iter_mut().zip([r, g, b]) {
+                *sum += channel as u32;
+            }
+        }
+    }
+
+    let dominant = groups.values().max_by_key(|group| group.count)?.mean();
+    // Vivid colors stand out even if they cover less of the image
+    let accent = groups
+        .values()
+        .map(|group| (group.mean(), group.count))
+        .filter(|&(color, _)| {
+            saturation(color) >= MIN_ACCENT_SATURATION && value(color) >= MIN_ACCENT_VALUE
+        })
+        .max_by(|(a, a_count), (b, b_count)| {
+            let score = |color, count| count as f64 * saturation(color).powi(2);
+            score(*a, *a_count).total_cmp(&score(*b, *b_count))
+        })
+        .map_or(dominant, |(color, _)| color);
+
+    Some(Palette {
+        dominant: rgba(dominant),
+        accent: rgba(accent),
+    })
+}
+
+fn value([r, g, b]: [f64; 3]) -> f64 {
+    r.max(g).max(b)
+}
+
+fn saturation(color @ [r, g, b]: [f64; 3]) -> f64 {
+    let max = value(color);
+    if max == 0.0 {
+        0.0
+    } else {
+        (max - r.min(g).min(b)) / max
+    }
+}
+
+fn rgba([r, g, b]: [f64; 3]) -> gdk::RGBA {
+    gdk::RGBA::new(r as f32, g as f32, b as f32, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAY: [u8; 4] = [128, 128, 128, 255];
+    const RED: [u8; 4] = [220, 30, 30, 255];
+    const DARK_RED: [u8; 4] = [40, 5, 5, 255];
+    const PALE_RED: [u8; 4] = [150, 130, 130, 255];
+    const CLEAR: [u8; 4] = [0, 0, 255, 0];
+
+    /// `R8g8b8a8` image with `color` of each pixel given by its position
+    fn image(width: usize, height: usize, color: impl Fn(usize, usize) -> [u8; 4]) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| color(x, y))
+            .collect()
+    }
+
+    fn color([r, g, b, _]: [u8; 4]) -> gdk::RGBA {
+        rgba([r, g, b].map(|channel| channel as f64 / 255.0))
+    }
+
+    #[test]
+    fn most_common_color_is_dominant_and_vivid_one_is_accent() {
+        // Red covers a quarter of the image
+        let data = image(100, 100, |x, y| if x < 50 && y < 50 { RED } else { GRAY });
+        let palette = analyze(&data, 100, 100, 400).unwrap();
+
+        assert_eq!(palette.dominant, color(GRAY));
+        assert_eq!(palette.accent, color(RED));
+    }
+
+    #[test]
+    fn dull_and_dark_colors_are_not_accents() {
+        let data = image(10, 10, |x, _| match x {
+            0..2 => DARK_RED,
+            2..4 => PALE_RED,
+            _ => GRAY,
+        });
+        let palette = analyze(&data, 10, 10, 40).unwrap();
+
+        assert_eq!(palette.dominant, color(GRAY));
+        assert_eq!(palette.accent, color(GRAY));
+    }
+
+    #[test]
+    fn transparent_pixels_are_skipped() {
+        let data = image(10, 10, |x, _| if x < 8 { CLEAR } else { RED });
+        let palette = analyze(&data, 10, 10, 40).unwrap();
+
+        assert_eq!(palette.dominant, color(RED));
+        assert_eq!(analyze(&image(10, 10, |_, _| CLEAR), 10, 10, 40), None);
+    }
+}
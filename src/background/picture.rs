@@ -0,0 +1,249 @@
+use std::cell::{Cell, RefCell};
+use std::f64::consts::SQRT_2;
+
+use gtk::gdk;
+use gtk::glib::{self, clone};
+use gtk::graphene;
+use gtk::gsk;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+
+use crate::config::BackgroundFit;
+
+use super::fill::Fill;
+
+/// Adjustments applied to background when rendering
+///
+/// All of them are supported by software renderer as well
+#[derive(Clone, Copy, Default)]
+pub struct Effects {
+    /// Radius of blur in pixels
+    pub blur: f64,
+    /// Opacity of black layer on top, from 0 to 1
+    pub dim: f64,
+    /// Amount of desaturation, from 0 to 1
+    pub grayscale: f64,
+    /// Opacity of black at the corners, from 0 to 1
+    pub vignette: f64,
+    pub tint: Option<gdk::RGBA>,
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct BackgroundPicture {
+        pub paintable: RefCell<Option<gdk::Paintable>>,
+        pub fit: Cell<BackgroundFit>,
+        pub fill: RefCell<Option<Fill>>,
+        pub effects: Cell<Effects>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BackgroundPicture {
+        const NAME: &'static str = "ShackleBackgroundPicture";
+        type Type = super::BackgroundPicture;
+        type ParentType = gtk::Widget;
+    }
+
+    impl ObjectImpl for BackgroundPicture {}
+
+    impl WidgetImpl for BackgroundPicture {
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            let widget = self.obj();
+            let width = widget.width() as f64;
+            let height = widget.height() as f64;
+            let bounds = graphene::Rect::new(0.0, 0.0, width as f32, height as f32);
+            let effects = self.effects.get();
+
+            // Blur fades edges into transparency, so background
+            // is rendered larger and cropped back to widget size
+            let margin = effects.blur;
+            snapshot.push_clip(&bounds);
+            if effects.grayscale > 0.0 {
+                snapshot.push_color_matrix(
+                    &grayscale_matrix(effects.grayscale as f32),
+                    &graphene::Vec4::zero(),
+                );
+            }
+            if margin > 0.0 {
+                snapshot.push_blur(effects.blur);
+                snapshot.translate(&graphene::Point::new(-margin as f32, -margin as f32));
+            }
+
+            let canvas_width = width + 2.0 * margin;
+            let canvas_height = height + 2.0 * margin;
+            if let Some(fill) = self.fill.borrow().as_ref() {
+                fill.snapshot(snapshot, canvas_width, canvas_height);
+            }
+            if let Some(paintable) = self.paintable.borrow().as_ref() {
+                snapshot_paintable(
+                    snapshot,
+                    paintable,
+                    self.fit.get(),
+                    canvas_width,
+                    canvas_height,
+                    widget.surface_scale(),
+                );
+            }
+
+            if margin > 0.0 {
+                snapshot.pop();
+            }
+            if effects.grayscale > 0.0 {
+                snapshot.pop();
+            }
+
+            if let Some(tint) = effects.tint {
+                snapshot.append_color(&tint, &bounds);
+            }
+            if effects.vignette > 0.0 {
+                append_vignette(snapshot, effects.vignette as f32, width, height);
+            }
+            if effects.dim > 0.0 {
+                snapshot.append_color(&gdk::RGBA::new(0.0, 0.0, 0.0, effects.dim as f32), &bounds);
+            }
+            snapshot.pop();
+        }
+    }
+}
+
+glib::wrapper! {
+    /// Image or video scaled according to [`BackgroundFit`]
+    /// on top of optional [`Fill`]
+    pub struct BackgroundPicture(ObjectSubclass<imp::BackgroundPicture>)
+        @extends gtk::Widget,
+        @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget;
+}
+
+impl BackgroundPicture {
+    pub fn new(
+        paintable: Option<&gdk::Paintable>,
+        fit: BackgroundFit,
+        fill: Option<Fill>,
+        effects: Effects,
+    ) -> Self {
+        let picture: Self = glib::Object::new();
+        let imp = picture.imp();
+        imp.fit.set(fit);
+        imp.fill.replace(fill);
+        imp.effects.set(effects);
+
+        if let Some(paintable) = paintable {
+            // Videos and animations change, so do sizes of videos once loaded
+            paintable.connect_invalidate_contents(clone!(
+                #[weak]
+                picture,
+                move |_| picture.queue_draw()
+            ));
+            paintable.connect_invalidate_size(clone!(
+                #[weak]
+                picture,
+                move |_| picture.queue_draw()
+            ));
+            imp.paintable.replace(Some(paintable.clone()));
+        }
+
+        picture
+    }
+
+    pub fn paintable(&self) -> Option<gdk::Paintable> {
+        self.imp().paintable.borrow().clone()
+    }
+
+    /// Number of physical pixels per logical one, used to
+    /// show images in their original size
+    fn surface_scale(&self) -> f64 {
+        self.native()
+            .and_then(|native| native.surface())
+            .map_or(self.scale_factor() as f64, |surface| surface.scale())
+    }
+}
+
+fn snapshot_paintable(
+    snapshot: &gtk::Snapshot,
+    paintable: &gdk::Paintable,
+    fit: BackgroundFit,
+    width: f64,
+    height: f64,
+    scale: f64,
+) {
+    let intrinsic_width = paintable.intrinsic_width() as f64;
+    let intrinsic_height = paintable.intrinsic_height() as f64;
+    let bounds = graphene::Rect::new(0.0, 0.0, width as f32, height as f32);
+
+    // Paintable has no size of its own, e.g. video not loaded yet
+    if intrinsic_width <= 0.0 || intrinsic_height <= 0.0 {
+        if matches!(fit, BackgroundFit::Fill) {
+            paintable.snapshot(snapshot, width, height);
+        }
+        return;
+    }
+
+    let contain = (width / intrinsic_width).min(height / intrinsic_height);
+    let factor = match fit {
+        BackgroundFit::Cover => (width / intrinsic_width).max(height / intrinsic_height),
+        BackgroundFit::Contain => contain,
+        BackgroundFit::ScaleDown => contain.min(1.0 / scale),
+        BackgroundFit::Centered | BackgroundFit::Tiled => 1.0 / scale,
+        BackgroundFit::Fill => {
+            paintable.snapshot(snapshot, width, height);
+            return;
+        }
+    };
+
+    let paint_width = intrinsic_width * factor;
+    let paint_height = intrinsic_height * factor;
+
+    snapshot.push_clip(&bounds);
+
+    if matches!(fit, BackgroundFit::Tiled) {
+        let tile = graphene::Rect::new(0.0, 0.0, paint_width as f32, paint_height as f32);
+        snapshot.push_repeat(&bounds, Some(&tile));
+        paintable.snapshot(snapshot, paint_width, paint_height);
+        snapshot.pop();
+    } else {
+        snapshot.save();
+        snapshot.translate(&graphene::Point::new(
+            ((width - paint_width) / 2.0) as f32,
+            ((height - paint_height) / 2.0) as f32,
+        ));
+        paintable.snapshot(snapshot, paint_width, paint_height);
+        snapshot.restore();
+    }
+
+    snapshot.pop();
+}
+
+/// Same matrix as used by CSS `grayscale()` filter
+fn grayscale_matrix(amount: f32) -> graphene::Matrix {
+    #[rustfmt::skip]
+    let matrix = graphene::Matrix::from_float([
+        1.0 - 0.7874 * amount, 0.2126 * amount,       0.2126 * amount,       0.0,
+        0.7152 * amount,       1.0 - 0.2848 * amount, 0.7152 * amount,       0.0,
+        0.0722 * amount,       0.0722 * amount,       1.0 - 0.9278 * amount, 0.0,
+        0.0,                   0.0,                   0.0,                   1.0,
+    ]);
+    matrix
+}
+
+/// Darken edges with radial gradient reaching
+/// `strength` opacity at the corners
+fn append_vignette(snapshot: &gtk::Snapshot, strength: f32, width: f64, height: f64) {
+    let bounds = graphene::Rect::new(0.0, 0.0, width as f32, height as f32);
+    let transparent = gdk::RGBA::new(0.0, 0.0, 0.0, 0.0);
+    let black = gdk::RGBA::new(0.0, 0.0, 0.0, strength);
+
+    snapshot.append_radial_gradient(
+        &bounds,
+        &graphene::Point::new((width / 2.0) as f32, (height / 2.0) as f32),
+        (width / 2.0 * SQRT_2) as f32,
+        (height / 2.0 * SQRT_2) as f32,
+        0.0,
+        1.0,
+        &[
+            gsk::ColorStop::new(0.4, transparent),
+            gsk::ColorStop::new(1.0, black),
+        ],
+    );
+}
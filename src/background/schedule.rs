@@ -0,0 +1,210 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gtk::glib;
+use log::warn;
+
+use crate::config::ScheduleConfig;
+
+/// Sun is considered up while its center is higher than this,
+/// accounting for atmospheric refraction and size of the disc
+const HORIZON_ELEVATION: f64 = -0.833;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Period {
+    Day,
+    Night,
+}
+
+/// Validated `[background.schedule]`
+pub enum Schedule {
+    /// Day lasts from sunrise to sunset at given location
+    Sun { latitude: f64, longitude: f64 },
+    /// Day lasts between fixed minutes of local day
+    Fixed { day_start: u32, night_start: u32 },
+}
+
+impl Schedule {
+    pub fn new(config: &ScheduleConfig) -> Self {
+        match (config.latitude, config.longitude) {
+            (Some(latitude), Some(longitude))
+                if (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude) =>
+            {
+                return Self::Sun {
+                    latitude,
+                    longitude,
+                };
+            }
+            (None, None) => (),
+            _ => warn!(
+                "background.schedule needs both latitude and longitude in degrees, using fixed times"
+            ),
+        }
+
+        Self::Fixed {
+            day_start: parse_time("background.schedule.sunrise", &config.sunrise, 7 * 60),
+            night_start: parse_time("background.schedule.sunset", &config.sunset, 19 * 60),
+        }
+    }
+
+    pub fn period(&self) -> Period {
+        let day = match *self {
+            Self::Sun {
+                latitude,
+                longitude,
+            } => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                sun_elevation(now.as_secs_f64(), latitude, longitude) > HORIZON_ELEVATION
+            }
+            Self::Fixed {
+                day_start,
+                night_start,
+            } => {
+                let now = glib::DateTime::now_local()
+                    .map(|now| (now.hour() * 60 + now.minute()) as u32)
+                    .unwrap_or(day_start);
+                is_day_between(day_start, night_start, now)
+            }
+        };
+
+        if day {
+            Period::Day
+        } else {
+            Period::Night
+        }
+    }
+}
+
+/// Whether `now` falls into day starting at `day_start` and ending
+/// at `night_start`, all in minutes since midnight
+fn is_day_between(day_start: u32, night_start: u32, now: u32) -> bool {
+    if day_start <= night_start {
+        (day_start..night_start).contains(&now)
+    } else {
+        // Day wraps over midnight
+        now >= day_start || now < night_start
+    }
+}
+
+/// Minutes since midnight of `"HH:MM"`
+fn parse_time(key: &str, time: &str, default: u32) -> u32 {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let hours: u32 = hours.trim().parse().ok()?;
+        let minutes: u32 = minutes.trim().parse().ok()?;
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    });
+
+    parsed.unwrap_or_else(|| {
+        warn!("Ignoring {key}: \"{time}\" is not a valid time, expected HH:MM");
+        default
+    })
+}
+
+/// Elevation of the sun above horizon in degrees at `unix_time`
+/// seen from given location, as calculated by NOAA solar calculator
+fn sun_elevation(unix_time: f64, latitude: f64, longitude: f64) -> f64 {
+    let julian_day = unix_time / 86400.0 + 2440587.5;
+    let t = (julian_day - 2451545.0) / 36525.0;
+
+    let mean_longitude = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.0);
+    let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+    let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+
+    let m = mean_anomaly.to_radians();
+    let center = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+        + (2.0 * m).sin() * (0.019993 - 0.000101 * t)
+        + (3.0 * m).sin() * 0.000289;
+
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    let apparent_longitude = mean_longitude + center - 0.00569 - 0.00478 * omega.sin();
+
+    let mean_obliquity =
+        23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+
+    let declination = (obliquity.sin() * apparent_longitude.to_radians().sin()).asin();
+
+    let l = mean_longitude.to_radians();
+    let y = (obliquity / 2.0).tan().powi(2);
+    let equation_of_time = 4.0
+        * (y * (2.0 * l).sin() - 2.0 * eccentricity * m.sin()
+            + 4.0 * eccentricity * y * m.sin() * (2.0 * l).cos()
+            - 0.5 * y * y * (4.0 * l).sin()
+            - 1.25 * eccentricity * eccentricity * (2.0 * m).sin())
+        .to_degrees();
+
+    let minutes_utc = (unix_time / 60.0).rem_euclid(24.0 * 60.0);
+    let true_solar_time =
+        (minutes_utc + equation_of_time + 4.0 * longitude).rem_euclid(24.0 * 60.0);
+    let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+    let latitude = latitude.to_radians();
+    let zenith = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour_angle.cos())
+    .clamp(-1.0, 1.0)
+    .acos();
+
+    90.0 - zenith.to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Royal Observatory, Greenwich
+    const GREENWICH: (f64, f64) = (51.4769, 0.0);
+
+    fn elevation(unix_time: f64) -> f64 {
+        sun_elevation(unix_time, GREENWICH.0, GREENWICH.1)
+    }
+
+    #[test]
+    fn sun_rises_and_sets_on_time() {
+        // On 2024-06-21 the sun rises at 03:43 UTC
+        // and sets at 20:21 UTC in Greenwich
+        assert!(elevation(1718940780.0) < HORIZON_ELEVATION); // 03:33
+        assert!(elevation(1718941980.0) > HORIZON_ELEVATION); // 03:53
+        assert!(elevation(1719000660.0) > HORIZON_ELEVATION); // 20:11
+        assert!(elevation(1719001860.0) < HORIZON_ELEVATION); // 20:31
+
+        // Solar noon at 12:02 UTC, 90° - latitude + tilt of Earth's axis
+        assert!((elevation(1718971320.0) - 61.96).abs() < 0.1);
+    }
+
+    #[test]
+    fn parses_valid_times() {
+        assert_eq!(parse_time("time", "00:00", 1), 0);
+        assert_eq!(parse_time("time", "7:05", 1), 7 * 60 + 5);
+        assert_eq!(parse_time("time", " 23:59 ", 1), 23 * 60 + 59);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for time in ["24:00", "7:60", "7", "7:", "-1:00", "seven:00", ""] {
+            assert_eq!(parse_time("time", time, 1), 1, "{time}");
+        }
+    }
+
+    #[test]
+    fn day_within_single_day() {
+        let (day_start, night_start) = (7 * 60, 19 * 60);
+        assert!(!is_day_between(day_start, night_start, 0));
+        assert!(!is_day_between(day_start, night_start, 7 * 60 - 1));
+        assert!(is_day_between(day_start, night_start, 7 * 60));
+        assert!(is_day_between(day_start, night_start, 19 * 60 - 1));
+        assert!(!is_day_between(day_start, night_start, 19 * 60));
+    }
+
+    #[test]
+    fn day_wraps_over_midnight() {
+        let (day_start, night_start) = (22 * 60, 6 * 60);
+        assert!(is_day_between(day_start, night_start, 22 * 60));
+        assert!(is_day_between(day_start, night_start, 23 * 60 + 59));
+        assert!(is_day_between(day_start, night_start, 0));
+        assert!(is_day_between(day_start, night_start, 6 * 60 - 1));
+        assert!(!is_day_between(day_start, night_start, 6 * 60));
+        assert!(!is_day_between(day_start, night_start, 12 * 60));
+        assert!(!is_day_between(day_start, night_start, 22 * 60 - 1));
+    }
+}
@@ -0,0 +1,409 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
+use gtk::prelude::*;
+use gtk::{gdk, glib};
+use log::{info, warn};
+use nix::sys::memfd::{memfd_create, MFdFlags};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_buffer, wl_output, wl_registry, wl_shm, wl_shm_pool};
+use wayland_client::{delegate_noop, Connection, Dispatch, QueueHandle, WEnum};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
+};
+
+use crate::config::ScreenshotConfig;
+
+/// Monitors not captured by then use configured
+/// background, so that hung compositor does not
+/// leave lock screen without background
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Screenshots = Rc<HashMap<String, gdk::Texture>>;
+
+thread_local! {
+    /// Screenshots by connector name, taken once when
+    /// locking and kept for monitors reconnected later
+    static SCREENSHOTS: RefCell<Option<Shared<LocalBoxFuture<'static, Screenshots>>>> =
+        const { RefCell::new(None) };
+}
+
+/// Start capturing contents of all monitors
+///
+/// Capture runs on its own Wayland connection in a separate
+/// thread, so locking does not wait for it. Compositors hiding
+/// the session as soon as locking is requested may capture
+/// the lock screen instead
+pub fn capture_screenshots(config: &ScreenshotConfig) {
+    let pixelate = config.pixelate;
+    let (sender, receiver) = oneshot::channel();
+    let spawned = thread::Builder::new().spawn(move || {
+        let frames = capture(pixelate).unwrap_or_else(|err| {
+            warn!("Failed to capture screenshots, using configured background: {err}");
+            HashMap::new()
+        });
+        // Receiver is gone if session got unlocked meanwhile
+        let _ = sender.send(frames);
+    });
+    if let Err(err) = spawned {
+        warn!("Failed to capture screenshots, using configured background: {err}");
+        return;
+    }
+
+    let screenshots = receiver
+        .map(|frames| {
+            let textures = frames
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(connector, frame)| (connector, frame.texture()))
+                .collect();
+            Rc::new(textures)
+        })
+        .boxed_local()
+        .shared();
+    SCREENSHOTS.set(Some(screenshots));
+}
+
+/// Screenshot of `monitor` once it is captured
+///
+/// Returns [`None`] if screenshots were not requested,
+/// capture failed or did not finish in time
+pub async fn screenshot(monitor: &gdk::Monitor) -> Option<gdk::Texture> {
+    let connector = monitor.connector()?;
+    let screenshots = SCREENSHOTS.with_borrow(Clone::clone)?;
+
+    let Ok(screenshots) = glib::future_with_timeout(CAPTURE_TIMEOUT, screenshots).await else {
+        warn!("Capturing {connector} timed out, using configured background");
+        return None;
+    };
+
+    screenshots.get(connector.as_str()).cloned()
+}
+
+/// Pixels of a captured monitor
+struct Frame {
+    width: i32,
+    height: i32,
+    stride: usize,
+    format: gdk::MemoryFormat,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn texture(self) -> gdk::Texture {
+        gdk::MemoryTexture::new(
+            self.width,
+            self.height,
+            self.format,
+            &glib::Bytes::from_owned(self.data),
+            self.stride,
+        )
+        .upcast()
+    }
+
+    /// Replace blocks of `block` pixels with their average color
+    fn pixelate(&mut self, block: usize) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let stride = self.stride;
+
+        for top in (0..height).step_by(block) {
+            for left in (0..width).step_by(block) {
+                // Offsets of pixels of the block
+                let pixels: Vec<usize> = (top..(top + block).min(height))
+                    .flat_map(|row| {
+                        (left..(left + block).min(width))
+                            .map(move |column| row * stride + column * 4)
+                    })
+                    .collect();
+
+                let mut sum = [0u64; 4];
+                for &pixel in &pixels {
+                    for (channel, sum) in sum.iter_mut().enumerate() {
+                        *sum += self.data[pixel + channel] as u64;
+                    }
+                }
+
+                let average = sum.map(|sum| (sum / pixels.len() as u64) as u8);
+                for &pixel in &pixels {
+                    self.data[pixel..pixel + 4].copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
+
+/// Monitor being captured
+struct Output {
+    output: wl_output::WlOutput,
+    /// Connector name, known once compositor sends it
+    name: Option<String>,
+    /// Memory compositor copies monitor contents to
+    buffer: Option<(wl_buffer::WlBuffer, File, Frame)>,
+    y_invert: bool,
+    done: bool,
+}
+
+struct State {
+    shm: wl_shm::WlShm,
+    outputs: Vec<Output>,
+    pixelate: u32,
+    frames: HashMap<String, Frame>,
+}
+
+/// Capture all monitors with `wlr-screencopy` protocol,
+/// blocking until compositor copies all of them
+fn capture(pixelate: u32) -> Result<HashMap<String, Frame>, String> {
+    let connection = Connection::connect_to_env().map_err(|err| err.to_string())?;
+    let (globals, mut queue) =
+        registry_queue_init::<State>(&connection).map_err(|err| err.to_string())?;
+    let qh = queue.handle();
+
+    let manager: zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1 = globals
+        .bind(&qh, 1..=1, ())
+        .map_err(|_| "compositor does not support wlr-screencopy".to_owned())?;
+    let shm = globals
+        .bind(&qh, 1..=1, ())
+        .map_err(|err| err.to_string())?;
+
+    // Connector names are sent since version 4
+    let outputs = globals
+        .contents()
+        .clone_list()
+        .into_iter()
+        .filter(|global| global.interface == "wl_output" && global.version >= 4)
+        .enumerate()
+        .map(|(index, global)| Output {
+            output: globals.registry().bind(global.name, 4, &qh, index),
+            name: None,
+            buffer: None,
+            y_invert: false,
+            done: false,
+        })
+        .collect();
+
+    let mut state = State {
+        shm,
+        outputs,
+        pixelate,
+        frames: HashMap::new(),
+    };
+    queue.roundtrip(&mut state).map_err(|err| err.to_string())?;
+
+    for (index, output) in state.outputs.iter_mut().enumerate() {
+        if output.name.is_some() {
+            manager.capture_output(0, &output.output, &qh, index);
+        } else {
+            output.done = true;
+        }
+    }
+
+    while !state.outputs.iter().all(|output| output.done) {
+        queue
+            .blocking_dispatch(&mut state)
+            .map_err(|err| err.to_string())?;
+    }
+
+    manager.destroy();
+    Ok(state.frames)
+}
+
+impl State {
+    /// Allocate memory for contents of output
+    /// and ask compositor to copy them there
+    fn copy(
+        &self,
+        frame: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        format: wl_shm::Format,
+        width: u32,
+        height: u32,
+        stride: u32,
+        qh: &QueueHandle<Self>,
+    ) -> Result<(wl_buffer::WlBuffer, File, Frame), String> {
+        let memory_format = match format {
+            wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888 => gdk::MemoryFormat::B8g8r8x8,
+            wl_shm::Format::Xbgr8888 | wl_shm::Format::Abgr8888 => gdk::MemoryFormat::R8g8b8x8,
+            format => return Err(format!("unsupported format {format:?}")),
+        };
+        let size = stride as usize * height as usize;
+
+        let fd = memfd_create(c"shackle-screenshot", MFdFlags::MFD_CLOEXEC)
+            .map_err(|err| err.to_string())?;
+        let file = File::from(fd);
+        file.set_len(size as u64).map_err(|err| err.to_string())?;
+
+        let pool = self.shm.create_pool(file.as_fd(), size as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+            qh,
+            (),
+        );
+        pool.destroy();
+        frame.copy(&buffer);
+
+        let frame = Frame {
+            width: width as i32,
+            height: height as i32,
+            stride: stride as usize,
+            format: memory_format,
+            data: Vec::new(),
+        };
+        Ok((buffer, file, frame))
+    }
+
+    /// Read contents of output `index` copied by compositor
+    fn finish(&mut self, index: usize) -> Result<(), String> {
+        let output = &mut self.outputs[index];
+        let (buffer, file, mut frame) = output.buffer.take().ok_or("compositor sent no buffer")?;
+        buffer.destroy();
+
+        frame.data = vec![0; frame.stride * frame.height as usize];
+        file.read_exact_at(&mut frame.data, 0)
+            .map_err(|err| err.to_string())?;
+
+        if output.y_invert {
+            let rows: Vec<&[u8]> = frame.data.chunks(frame.stride).rev().collect();
+            frame.data = rows.concat();
+        }
+        if self.pixelate > 1 {
+            frame.pixelate(self.pixelate as usize);
+        }
+
+        let name = output.name.clone().unwrap_or_default();
+        info!("Captured screenshot of {name}");
+        self.frames.insert(name, frame);
+        Ok(())
+    }
+}
+
+impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        frame: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        index: &usize,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let index = *index;
+        let result = match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format,
+                width,
+                height,
+                stride,
+            } => match format {
+                WEnum::Value(format) => state
+                    .copy(frame, format, width, height, stride, qh)
+                    .map(|buffer| state.outputs[index].buffer = Some(buffer)),
+                WEnum::Unknown(format) => Err(format!("unknown format {format:#x}")),
+            },
+            zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                state.outputs[index].y_invert = flags
+                    .into_result()
+                    .is_ok_and(|flags| flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert));
+                return;
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                let result = state.finish(index);
+                state.outputs[index].done = true;
+                frame.destroy();
+                result
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                state.outputs[index].done = true;
+                frame.destroy();
+                Err("compositor failed to copy it".to_owned())
+            }
+            _ => return,
+        };
+
+        if let Err(err) = result {
+            let output = &mut state.outputs[index];
+            warn!(
+                "Failed to capture {}: {err}",
+                output.name.as_deref().unwrap_or_default()
+            );
+            if !output.done {
+                output.done = true;
+                frame.destroy();
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_output::WlOutput,
+        event: wl_output::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            state.outputs[*index].name = Some(name);
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Monitors connected during capture are not captured
+    }
+}
+
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: ignore wl_buffer::WlBuffer);
+delegate_noop!(State: zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_are_averaged_per_channel() {
+        // 3x2 pixels with padding at the end of rows,
+        // so that the last column is a block of its own
+        #[rustfmt::skip]
+        let data = vec![
+            0, 10, 20, 255,   40, 50, 60, 255,   7, 7, 7, 255,   1, 1,
+            0, 30, 40, 255,   40, 70, 80, 255,   9, 9, 9, 255,   1, 1,
+        ];
+        let mut frame = Frame {
+            width: 3,
+            height: 2,
+            stride: 14,
+            format: gdk::MemoryFormat::B8g8r8x8,
+            data,
+        };
+
+        frame.pixelate(2);
+
+        #[rustfmt::skip]
+        assert_eq!(frame.data, [
+            20, 40, 50, 255,   20, 40, 50, 255,   8, 8, 8, 255,   1, 1,
+            20, 40, 50, 255,   20, 40, 50, 255,   8, 8, 8, 255,   1, 1,
+        ]);
+    }
+}
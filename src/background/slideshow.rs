@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use gtk::gio;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use log::warn;
+
+use crate::config::{SlideshowConfig, SlideshowOrder};
+
+use super::load::{self, pick, Candidate, Scan};
+use super::matching::{best_fitting, Target};
+use super::{picture, show, Appearance};
+
+/// Periodically switches background of a stack to
+/// another file from background sources
+pub struct Slideshow {
+    stack: glib::WeakRef<gtk::Stack>,
+    sources: Vec<PathBuf>,
+    weights: HashMap<PathBuf, f64>,
+    target: Option<Target>,
+    appearance: Appearance,
+    interval: Duration,
+    order: SlideshowOrder,
+    /// Files of `sources`, rescanned only once they change
+    scan: RefCell<Option<Scan>>,
+    /// File shown or attempted to be shown last
+    current: RefCell<Option<PathBuf>>,
+}
+
+impl Slideshow {
+    /// Show first background from `sources` and start switching them.
+    /// Slideshow stops once `stack` is destroyed
+    pub fn start(
+        stack: &gtk::Stack,
+        sources: &[PathBuf],
+        weights: &HashMap<PathBuf, f64>,
+        target: Option<Target>,
+        appearance: Appearance,
+        config: &SlideshowConfig,
+    ) {
+        stack.set_transition_duration((config.crossfade.max(0.0) * 1000.0) as u32);
+
+        let interval = Duration::try_from_secs_f64(config.interval * 60.0)
+            .ok()
+            .filter(|interval| !interval.is_zero());
+
+        let slideshow = Rc::new(Self {
+            stack: stack.downgrade(),
+            sources: sources.to_owned(),
+            weights: weights.clone(),
+            target,
+            appearance,
+            interval: interval.unwrap_or_default(),
+            order: config.order,
+            scan: RefCell::new(None),
+            current: RefCell::new(None),
+        });
+        slideshow.show_next();
+
+        if interval.is_some() {
+            slideshow.schedule_next();
+        } else {
+            warn!(
+                "Slideshow interval must be positive, got {}. Background will not change",
+                config.interval
+            );
+        }
+    }
+
+    fn schedule_next(self: &Rc<Self>) {
+        let slideshow = self.clone();
+        glib::timeout_add_local_once(self.interval, move || {
+            let Some(stack) = slideshow.stack.upgrade() else {
+                return;
+            };
+
+            // Frame clock does not tick while output is powered off,
+            // so switching is postponed until it is back on
+            stack.add_tick_callback(move |_, _| {
+                slideshow.show_next();
+                slideshow.schedule_next();
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    fn show_next(self: &Rc<Self>) {
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = slideshow)]
+            self,
+            async move { slideshow.advance().await }
+        ));
+    }
+
+    async fn advance(&self) {
+        let current = self.current.borrow().clone();
+
+        let sources = self.sources.clone();
+        let weights = self.weights.clone();
+        let (target, order) = (self.target, self.order);
+        let previous = current.clone();
+        let mut scan = self.scan.take();
+        let next = gio::spawn_blocking(move || {
+            let next = next_candidate(
+                &sources,
+                &weights,
+                target,
+                order,
+                &mut scan,
+                previous.as_deref(),
+            );
+            (scan, next)
+        })
+        .await;
+
+        let Ok((scan, Some(next))) = next else {
+            return;
+        };
+        self.scan.replace(scan);
+        if current.as_ref() == Some(&next.path) {
+            // Nothing else to show
+            return;
+        }
+
+        let paintable = next.load(self.appearance.scale_to(self.target)).await;
+        self.current.replace(Some(next.path));
+        let Some(stack) = self.stack.upgrade() else {
+            return;
+        };
+
+        // Broken file does not replace working background
+        if paintable.is_none() && stack.visible_child().is_some() {
+            return;
+        }
+        show(&stack, &picture(paintable.as_ref(), &self.appearance));
+    }
+}
+
+/// File to show after `current`, rescanning
+/// `sources` if they changed since `scan`
+fn next_candidate(
+    sources: &[PathBuf],
+    weights: &HashMap<PathBuf, f64>,
+    target: Option<Target>,
+    order: SlideshowOrder,
+    scan: &mut Option<Scan>,
+    current: Option<&Path>,
+) -> Option<Candidate> {
+    if scan.as_ref().is_none_or(Scan::is_outdated) {
+        *scan = Some(load::scan(sources, weights, target));
+    }
+    let candidates: Vec<&Candidate> = scan.as_ref()?.candidates.iter().collect();
+
+    match order {
+        // History is shared by all monitors, so it may
+        // have another monitor's pick as the latest one
+        SlideshowOrder::Random => {
+            let others: Vec<&Candidate> = candidates
+                .iter()
+                .copied()
+                .filter(|candidate| Some(candidate.path.as_path()) != current)
+                .collect();
+
+            pick(&others, target)
+                .or(candidates.first().copied())
+                .cloned()
+        }
+        SlideshowOrder::Sorted => {
+            let mut candidates = best_fitting(&candidates, target);
+            candidates.sort_by(|a, b| a.path.cmp(&b.path));
+
+            candidates
+                .iter()
+                .find(|candidate| current.is_none_or(|current| candidate.path.as_path() > current))
+                .or(candidates.first())
+                .copied()
+                .cloned()
+        }
+    }
+}
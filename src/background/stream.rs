@@ -0,0 +1,295 @@
+use std::cell::{Cell, RefCell};
+
+use gst::prelude::*;
+use gst_app::AppSink;
+use gtk::gdk;
+use gtk::glib;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use log::warn;
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct VideoStream {
+        pub playbin: RefCell<Option<gst::Element>>,
+        pub bus_watch: RefCell<Option<gst::bus::BusWatchGuard>>,
+        /// Last decoded frame
+        pub frame: RefCell<Option<gdk::Texture>>,
+        /// Seek was requested by GTK rather than done to loop
+        pub seeking: Cell<bool>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for VideoStream {
+        const NAME: &'static str = "ShackleVideoStream";
+        type Type = super::VideoStream;
+        type ParentType = gtk::MediaStream;
+        type Interfaces = (gdk::Paintable,);
+    }
+
+    impl ObjectImpl for VideoStream {
+        fn dispose(&self) {
+            self.bus_watch.take();
+            if let Some(playbin) = self.playbin.take() {
+                let _ = playbin.set_state(gst::State::Null);
+            }
+        }
+    }
+
+    impl MediaStreamImpl for VideoStream {
+        fn play(&self) -> bool {
+            self.set_state(gst::State::Playing)
+        }
+
+        fn pause(&self) {
+            self.set_state(gst::State::Paused);
+        }
+
+        fn seek(&self, timestamp: i64) {
+            let obj = self.obj();
+            let Some(playbin) = self.playbin.borrow().clone() else {
+                obj.seek_failed();
+                return;
+            };
+
+            let position = gst::ClockTime::from_useconds(timestamp.max(0) as u64);
+            match playbin.seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE, position) {
+                Ok(()) => self.seeking.set(true),
+                Err(_) => obj.seek_failed(),
+            }
+        }
+
+        fn update_audio(&self, muted: bool, volume: f64) {
+            if let Some(playbin) = self.playbin.borrow().as_ref() {
+                playbin.set_property("mute", muted);
+                playbin.set_property("volume", volume);
+            }
+        }
+    }
+
+    impl PaintableImpl for VideoStream {
+        fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+            if let Some(frame) = self.frame.borrow().as_ref() {
+                frame.snapshot(snapshot, width, height);
+            }
+        }
+
+        fn current_image(&self) -> gdk::Paintable {
+            match self.frame.borrow().as_ref() {
+                Some(frame) => frame.clone().upcast(),
+                None => gdk::Paintable::new_empty(0, 0),
+            }
+        }
+
+        fn intrinsic_width(&self) -> i32 {
+            self.frame
+                .borrow()
+                .as_ref()
+                .map_or(0, |frame| frame.width())
+        }
+
+        fn intrinsic_height(&self) -> i32 {
+            self.frame
+                .borrow()
+                .as_ref()
+                .map_or(0, |frame| frame.height())
+        }
+    }
+
+    impl VideoStream {
+        fn set_state(&self, state: gst::State) -> bool {
+            let Some(playbin) = self.playbin.borrow().clone() else {
+                return false;
+            };
+            playbin.set_state(state).is_ok()
+        }
+    }
+}
+
+glib::wrapper! {
+    /// Video decoded by GStreamer no larger than requested
+    ///
+    /// Unlike [`gtk::MediaFile`] frames are scaled down before
+    /// they reach GTK, so large videos do not take memory and
+    /// upload bandwidth needed for their full resolution
+    pub struct VideoStream(ObjectSubclass<imp::VideoStream>)
+        @extends gtk::MediaStream,
+        @implements gdk::Paintable;
+}
+
+impl VideoStream {
+    /// Decoded frames fit into `max_size` × `max_size` square,
+    /// keeping aspect ratio. Smaller videos are not scaled up
+    pub fn new(uri: &str, max_size: Option<u32>) -> Result<Self, glib::BoolError> {
+        gst::init().map_err(|err| glib::bool_error!("{err}"))?;
+
+        let stream: Self = glib::Object::new();
+
+        let size = gst::IntRange::new(1, max_size.map_or(i32::MAX, |size| size as i32));
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("format", "RGBx")
+            .field("width", size)
+            .field("height", size)
+            .field("pixel-aspect-ratio", gst::Fraction::new(1, 1))
+            .build();
+        let sink = AppSink::builder()
+            .caps(&caps)
+            .max_buffers(1)
+            .drop(true)
+            .build();
+        sink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(frame_handler(&stream))
+                .build(),
+        );
+
+        let scale = gst::ElementFactory::make("videoscale").build()?;
+        let convert = gst::ElementFactory::make("videoconvert").build()?;
+        let video_sink = gst::Bin::new();
+        video_sink.add_many([&scale, &convert, sink.upcast_ref()])?;
+        gst::Element::link_many([&scale, &convert, sink.upcast_ref()])?;
+        let pad = scale
+            .static_pad("sink")
+            .ok_or_else(|| glib::bool_error!("videoscale has no sink pad"))?;
+        video_sink.add_pad(&gst::GhostPad::with_target(&pad)?)?;
+
+        let playbin = gst::ElementFactory::make("playbin")
+            .property("uri", uri)
+            .property("video-sink", &video_sink)
+            .build()?;
+
+        let bus = playbin
+            .bus()
+            .ok_or_else(|| glib::bool_error!("playbin has no bus"))?;
+        let watch = bus.add_watch_local(glib::clone!(
+            #[weak]
+            stream,
+            #[upgrade_or]
+            glib::ControlFlow::Break,
+            move |_, message| {
+                stream.handle_message(message);
+                glib::ControlFlow::Continue
+            }
+        ))?;
+
+        let imp = stream.imp();
+        imp.playbin.replace(Some(playbin.clone()));
+        imp.bus_watch.replace(Some(watch));
+
+        // Preroll, so that first frame is shown even if
+        // video never plays
+        playbin
+            .set_state(gst::State::Paused)
+            .map_err(|_| glib::bool_error!("Failed to start decoding"))?;
+
+        Ok(stream)
+    }
+
+    fn handle_message(&self, message: &gst::Message) {
+        let imp = self.imp();
+        let Some(playbin) = imp.playbin.borrow().clone() else {
+            return;
+        };
+
+        match message.view() {
+            gst::MessageView::Eos(_) => {
+                if self.is_loop() {
+                    // Not a seek requested by GTK, so it is not reported
+                    let _ = playbin.seek_simple(gst::SeekFlags::FLUSH, gst::ClockTime::ZERO);
+                } else {
+                    self.stream_ended();
+                }
+            }
+            gst::MessageView::Error(err) => {
+                warn!("Video decoding failed: {}", err.error());
+                let _ = playbin.set_state(gst::State::Null);
+                self.set_error(err.error());
+            }
+            gst::MessageView::AsyncDone(_) => {
+                if imp.seeking.replace(false) {
+                    self.seek_success();
+                }
+                if !self.is_prepared() {
+                    self.prepare(&playbin);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Report properties of stream to GTK once it has prerolled
+    fn prepare(&self, playbin: &gst::Element) {
+        let has_audio = playbin.property::<i32>("n-audio") > 0;
+        let duration = playbin
+            .query_duration::<gst::ClockTime>()
+            .map_or(0, |duration| duration.useconds() as i64);
+
+        let mut query = gst::query::Seeking::new(gst::Format::Time);
+        let seekable = playbin.query(&mut query) && query.result().0;
+
+        self.stream_prepared(has_audio, true, seekable, duration);
+        playbin.set_property("mute", self.is_muted());
+        playbin.set_property("volume", self.volume());
+        // Playback may have been requested before stream was ready
+        if self.is_playing() {
+            let _ = playbin.set_state(gst::State::Playing);
+        }
+    }
+
+    fn set_frame(&self, frame: gdk::Texture, timestamp: i64) {
+        let imp = self.imp();
+        let previous = imp.frame.replace(Some(frame.clone()));
+
+        let resized = previous.is_none_or(|previous| {
+            (previous.width(), previous.height()) != (frame.width(), frame.height())
+        });
+        if resized {
+            self.invalidate_size();
+        }
+        self.invalidate_contents();
+        self.update(timestamp);
+    }
+}
+
+/// Turns samples into textures on streaming thread
+/// and hands them to the stream on main thread
+fn frame_handler(
+    stream: &VideoStream,
+) -> impl FnMut(&AppSink) -> Result<gst::FlowSuccess, gst::FlowError> + Send + 'static {
+    let stream = glib::SendWeakRef::from(stream.downgrade());
+
+    move |sink| {
+        let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+        let info = sample
+            .caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+            .ok_or(gst::FlowError::NotNegotiated)?;
+        let buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+        let timestamp = buffer
+            .pts()
+            .map_or(0, |timestamp| timestamp.useconds() as i64);
+
+        // Frame memory is handed to GTK without copying
+        let data = buffer
+            .into_mapped_buffer_readable()
+            .map_err(|_| gst::FlowError::Error)?;
+        let frame = gdk::MemoryTexture::new(
+            info.width() as i32,
+            info.height() as i32,
+            gdk::MemoryFormat::R8g8b8x8,
+            &glib::Bytes::from_owned(data),
+            info.stride()[0] as usize,
+        );
+
+        let stream = stream.clone();
+        glib::MainContext::default().invoke(move || {
+            if let Some(stream) = stream.upgrade() {
+                stream.set_frame(frame.upcast(), timestamp);
+            }
+        });
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
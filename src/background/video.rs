@@ -0,0 +1,185 @@
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use log::{error, info};
+use zbus::proxy;
+
+use crate::config::{config, OnBattery};
+
+/// Monitor is considered blanked once it stops
+/// presenting frames for this long
+const BLANK_TIMEOUT: Duration = Duration::from_secs(2);
+const BLANK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+thread_local! {
+    static ON_BATTERY: Cell<bool> = const { Cell::new(false) };
+    static WATCHING_BATTERY: Cell<bool> = const { Cell::new(false) };
+    /// Videos currently shown, updated when power source changes
+    static VIDEOS: RefCell<Vec<Weak<ManagedVideo>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Video shown by `widget`, paused and resumed
+/// according to `[background.video]`
+struct ManagedVideo {
+    video: gtk::MediaStream,
+    widget: glib::WeakRef<gtk::Widget>,
+    blanked: Cell<bool>,
+    last_frame: Cell<Instant>,
+    tick: RefCell<Option<gtk::TickCallbackId>>,
+}
+
+/// Apply `[background.video]` to `video` for as long as `widget` exists
+pub fn manage(widget: &gtk::Widget, video: &gtk::MediaStream) {
+    let config = config();
+    video.set_muted(config.background.video.mute);
+
+    if config.background.video.on_battery != OnBattery::Play {
+        watch_battery();
+    }
+
+    let managed = Rc::new(ManagedVideo {
+        video: video.clone(),
+        widget: widget.downgrade(),
+        blanked: Cell::new(false),
+        last_frame: Cell::new(Instant::now()),
+        tick: RefCell::new(None),
+    });
+    VIDEOS.with_borrow_mut(|videos| {
+        videos.retain(|video| video.strong_count() > 0);
+        videos.push(Rc::downgrade(&managed));
+    });
+    managed.update();
+
+    // Timer owns the video state and stops once widget is gone
+    glib::timeout_add_local(BLANK_CHECK_INTERVAL, move || {
+        if managed.widget.upgrade().is_none() {
+            return glib::ControlFlow::Break;
+        }
+
+        let stalled = managed.last_frame.get().elapsed() >= BLANK_TIMEOUT;
+        if managed.tick.borrow().is_some() && stalled && !managed.blanked.get() {
+            info!("Monitor blanked, pausing video");
+            managed.blanked.set(true);
+            managed.update();
+        }
+
+        glib::ControlFlow::Continue
+    });
+}
+
+impl ManagedVideo {
+    fn update(self: &Rc<Self>) {
+        let config = config();
+        let config = &config.background.video;
+
+        let on_battery = ON_BATTERY.get() && config.on_battery != OnBattery::Play;
+        if on_battery && config.on_battery == OnBattery::FirstFrame {
+            self.video.seek(0);
+        }
+        self.video.set_playing(!on_battery && !self.blanked.get());
+
+        // Frames are only needed to detect blanking while
+        // video plays, requesting them otherwise wastes power
+        let watch_frames = config.pause_when_blanked && !on_battery;
+        if !watch_frames {
+            self.blanked.set(false);
+            if let Some(tick) = self.tick.take() {
+                tick.remove();
+            }
+            return;
+        }
+
+        if self.tick.borrow().is_some() {
+            return;
+        }
+        let Some(widget) = self.widget.upgrade() else {
+            return;
+        };
+
+        self.last_frame.set(Instant::now());
+        // Compositor stops sending frame callbacks while output
+        // is off, so ticks stall until it is back on
+        let tick = widget.add_tick_callback(clone!(
+            #[weak(rename_to = managed)]
+            self,
+            #[upgrade_or]
+            glib::ControlFlow::Break,
+            move |_, _| {
+                managed.last_frame.set(Instant::now());
+                if managed.blanked.replace(false) {
+                    info!("Monitor unblanked, resuming video");
+                    managed.update();
+                }
+                glib::ControlFlow::Continue
+            }
+        ));
+        self.tick.replace(Some(tick));
+    }
+}
+
+impl Drop for ManagedVideo {
+    fn drop(&mut self) {
+        if let Some(tick) = self.tick.take() {
+            tick.remove();
+        }
+    }
+}
+
+/// Track power source reported by UPower
+fn watch_battery() {
+    if WATCHING_BATTERY.replace(true) {
+        return;
+    }
+
+    glib::spawn_future_local(async {
+        let Ok(connection) = zbus::Connection::system().await else {
+            error!("Failed to connect to system bus.");
+            return;
+        };
+
+        let Ok(upower) = UPowerProxy::new(&connection).await else {
+            error!("Failed to connect to UPower.");
+            return;
+        };
+
+        let mut changes = upower.receive_on_battery_changed().await;
+        set_on_battery(upower.on_battery().await.unwrap_or(false));
+
+        while let Some(change) = changes.next().await {
+            if let Ok(on_battery) = change.get().await {
+                set_on_battery(on_battery);
+            }
+        }
+    });
+}
+
+fn set_on_battery(on_battery: bool) {
+    if ON_BATTERY.replace(on_battery) == on_battery {
+        return;
+    }
+
+    info!(
+        "Running on {}",
+        if on_battery { "battery" } else { "AC power" }
+    );
+
+    let videos: Vec<Rc<ManagedVideo>> =
+        VIDEOS.with_borrow(|videos| videos.iter().filter_map(Weak::upgrade).collect());
+    for video in videos {
+        video.update();
+    }
+}
+
+#[proxy(
+    interface = "org.freedesktop.UPower",
+    default_path = "/org/freedesktop/UPower",
+    default_service = "org.freedesktop.UPower"
+)]
+pub trait UPower {
+    #[zbus(property)]
+    fn on_battery(&self) -> zbus::Result<bool>;
+}
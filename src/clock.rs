@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+use futures::StreamExt;
+use gtk::glib;
+use gtk::prelude::*;
+use log::{info, warn};
+
+use crate::auth::fprint::Login1ManagerProxy;
+use crate::config::ClockConfig;
+
+pub const DEFAULT_DATE_FORMAT: &str = "%A, %B %-d";
+
+/// Labels of clock widget and formats of their text
+struct Clock {
+    time: glib::WeakRef<gtk::Label>,
+    date: glib::WeakRef<gtk::Label>,
+    time_format: String,
+    date_format: String,
+    seconds: bool,
+    /// Pending update on the next minute or second boundary
+    source: RefCell<Option<glib::SourceId>>,
+}
+
+impl Clock {
+    /// Refresh labels, returns `false` once they are destroyed
+    fn update(&self) -> bool {
+        let (Some(time), Some(date)) = (self.time.upgrade(), self.date.upgrade()) else {
+            return false;
+        };
+        let Ok(now) = glib::DateTime::now_local() else {
+            return true;
+        };
+
+        if let Ok(text) = now.format(&self.time_format) {
+            time.set_label(&text);
+        }
+        if let Ok(text) = now.format(&self.date_format) {
+            date.set_label(&text);
+        }
+
+        true
+    }
+
+    /// Schedule update exactly on the next boundary,
+    /// replacing update scheduled before
+    fn schedule_update(self: &Rc<Self>) {
+        let seconds = glib::DateTime::now_local().map_or(0.0, |now| now.seconds());
+        let period = if self.seconds { 1.0 } else { 60.0 };
+        let delay = Duration::from_secs_f64(period - seconds % period);
+
+        let clock = self.clone();
+        let source = glib::timeout_add_local_once(delay, move || {
+            clock.source.take();
+            if clock.update() {
+                clock.schedule_update();
+            }
+        });
+
+        if let Some(previous) = self.source.replace(Some(source)) {
+            previous.remove();
+        }
+    }
+}
+
+/// Time and date updated on minute boundaries,
+/// or second ones if seconds are shown
+pub fn clock(config: &ClockConfig) -> gtk::Widget {
+    let default_format = match (config.twelve_hour, config.seconds) {
+        (false, false) => "%H:%M",
+        (false, true) => "%H:%M:%S",
+        (true, false) => "%-I:%M %p",
+        (true, true) => "%-I:%M:%S %p",
+    };
+    let time_format = config.format.as_deref().map_or(default_format, |format| {
+        valid_format("ui.clock.format", format, default_format)
+    });
+    let date_format = valid_format(
+        "ui.clock.date_format",
+        &config.date_format,
+        DEFAULT_DATE_FORMAT,
+    );
+
+    let bbox = gtk::Box::builder()
+        .css_classes(["clock"])
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    let time = gtk::Label::builder().css_classes(["clock-time"]).build();
+    let date = gtk::Label::builder()
+        .css_classes(["clock-date"])
+        .visible(!date_format.is_empty())
+        .build();
+    bbox.append(&time);
+    bbox.append(&date);
+
+    let clock = Rc::new(Clock {
+        time: time.downgrade(),
+        date: date.downgrade(),
+        time_format: time_format.to_owned(),
+        date_format: date_format.to_owned(),
+        seconds: config.seconds,
+        source: RefCell::new(None),
+    });
+    clock.update();
+    clock.schedule_update();
+
+    glib::spawn_future_local(update_on_wakeup(Rc::downgrade(&clock)));
+
+    bbox.into()
+}
+
+/// `format` if GLib accepts it, `default` otherwise
+fn valid_format<'a>(key: &str, format: &'a str, default: &'a str) -> &'a str {
+    let valid = glib::DateTime::now_local().is_ok_and(|now| now.format(format).is_ok());
+    if valid {
+        format
+    } else {
+        warn!("Ignoring {key}: \"{format}\" is not a valid time format");
+        default
+    }
+}
+
+/// Timers do not run while system is suspended, so after
+/// wakeup clock would lag until pending update fires
+async fn update_on_wakeup(clock: Weak<Clock>) {
+    let Ok(connection) = zbus::Connection::system().await else {
+        warn!("Failed to connect to system bus, clock may lag after suspend");
+        return;
+    };
+    let Ok(login_manager) = Login1ManagerProxy::new(&connection).await else {
+        warn!("Failed to connect to login1 manager, clock may lag after suspend");
+        return;
+    };
+    let Ok(mut prepare_for_sleep_stream) = login_manager.receive_prepare_for_sleep().await else {
+        warn!("Failed to watch for suspend, clock may lag after suspend");
+        return;
+    };
+
+    while let Some(msg) = prepare_for_sleep_stream.next().await {
+        let Some(clock) = clock.upgrade() else {
+            return;
+        };
+
+        // start == false means sleep ended
+        if msg.args().is_ok_and(|args| !args.start) {
+            info!("Woke up from suspend, updating clock");
+            if clock.update() {
+                clock.schedule_update();
+            }
+        }
+    }
+}
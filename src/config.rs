@@ -1,29 +1,624 @@
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, RwLock},
+};
 
 use clap::Parser;
+use log::{error, info};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 
-const CONFIG: LazyLock<Args> = LazyLock::new(|| Args::parse());
+use crate::clock::DEFAULT_DATE_FORMAT;
+use crate::dirs::config_dir;
 
-pub fn config() -> LazyLock<Args> {
-    CONFIG
+static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+static CONFIG: LazyLock<RwLock<Arc<Config>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(load_config(&ARGS))));
+
+/// Current configuration
+///
+/// Returned snapshot is not affected by [`reload_config`], call
+/// this function again to observe new values
+pub fn config() -> Arc<Config> {
+    CONFIG.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// Re-read config file and apply command line flags on top
+///
+/// On error current configuration is kept intact
+pub fn reload_config() -> Result<(), ConfigError> {
+    let mut config = match config_path(&ARGS) {
+        Some(path) => Config::from_file(&path)?,
+        None => Config::default(),
+    };
+    config.apply_args(&ARGS);
+
+    *CONFIG.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(config);
+    Ok(())
+}
+
+/// Location of config file that should be watched for changes.
+/// File is not guaranteed to exist
+pub fn config_file() -> Option<PathBuf> {
+    ARGS.config
+        .clone()
+        .or_else(|| config_dir().map(|dir| dir.join("config.toml")))
 }
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
-pub struct Args {
+struct Args {
+    /// Configuration file to use
+    ///
+    /// Defaults to $XDG_CONFIG_HOME/shackle/config.toml.
+    /// Flags passed on command line take precedence over the file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
     /// Fork off locker process
     #[arg(short, long)]
-    pub daemonize: bool,
+    daemonize: bool,
     /// Start fingerprint verification only after device wakes up
     ///
     /// Useful if fingerprint verification does not work (or is delayed)
     /// after devices goes to sleep
     #[arg(short, long)]
+    await_wakeup: bool,
+    /// Images, videos or directories to display on background
+    ///
+    /// Any image or video format supported by GTK and GStreamer
+    /// can be used. Directories are searched recursively and
+    /// paths may contain `*` and `?` wildcards. If more than one
+    /// file is found a random one will be selected
+    #[arg(short, long, num_args = 1..)]
+    background: Vec<PathBuf>,
+}
+
+/// Configuration read from `config.toml` with command
+/// line flags applied on top
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub background: BackgroundConfig,
+    pub auth: AuthConfig,
+    pub ui: UiConfig,
+    pub theme: ThemeConfig,
+    pub behaviour: BehaviourConfig,
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    /// Images, videos, directories or wildcard patterns
+    /// to display on background, either one or a list
+    #[serde(rename = "path", deserialize_with = "one_or_many")]
+    pub paths: Vec<PathBuf>,
+    /// Backgrounds for specific monitors, first matching rule wins.
+    /// Monitors not matched by any rule use `path`
+    #[serde(rename = "output")]
+    pub outputs: Vec<OutputBackground>,
+    /// Relative probability of picking files from subfolders of
+    /// background directories, e.g. `"nature/forest" = 2.0`.
+    /// Files outside of listed subfolders have weight of 1
+    pub weights: HashMap<PathBuf, f64>,
+    /// How images and videos are scaled to monitor
+    pub fit: BackgroundFit,
+    /// Solid color painted behind background, shown on its own
+    /// if no background is configured or it fails to load
+    pub color: Option<String>,
+    /// Same as `color`, but gradient. Takes precedence over `color`
+    pub gradient: Option<GradientConfig>,
+    /// Adjustments applied to images and videos when rendering
+    pub effects: EffectsConfig,
+    /// Playback of video backgrounds
+    pub video: VideoConfig,
+    /// Periodically switch to another file if background is a directory
+    pub slideshow: Option<SlideshowConfig>,
+    /// Separate backgrounds for day and night, used instead
+    /// of `path` when set. Switched while screen stays locked
+    pub schedule: Option<ScheduleConfig>,
+    /// Use screenshot of monitor taken when locking instead of
+    /// configured background, which is shown until screenshot is
+    /// ready. Requires compositor supporting `wlr-screencopy`
+    pub screenshot: Option<ScreenshotConfig>,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Location to calculate sunrise and sunset for, in degrees
+    pub latitude: Option<f64>,
+    /// Positive to the east of Greenwich
+    pub longitude: Option<f64>,
+    /// Local time day starts at, `"HH:MM"`. Only used if
+    /// location is not set
+    pub sunrise: String,
+    /// Local time night starts at, `"HH:MM"`. Only used if
+    /// location is not set
+    pub sunset: String,
+    /// Backgrounds shown between sunrise and sunset, same as `path`.
+    /// `path` is used if empty
+    #[serde(deserialize_with = "one_or_many")]
+    pub day: Vec<PathBuf>,
+    /// Backgrounds shown between sunset and sunrise, same as `path`.
+    /// `path` is used if empty
+    #[serde(deserialize_with = "one_or_many")]
+    pub night: Vec<PathBuf>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            latitude: None,
+            longitude: None,
+            sunrise: "07:00".to_owned(),
+            sunset: "19:00".to_owned(),
+            day: Vec::new(),
+            night: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreenshotConfig {
+    /// Radius of blur in pixels. Unless screenshot is pixelated by
+    /// blocks of at least 4 pixels, it is blurred by at least 5
+    /// pixels so that its text can not be read
+    pub blur: f64,
+    /// Darken screenshot, from 0 (unchanged) to 1 (black)
+    pub dim: f64,
+    /// Size of pixelation blocks in pixels, 0 disables pixelation
+    pub pixelate: u32,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self {
+            blur: 10.0,
+            dim: 0.2,
+            pixelate: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlideshowConfig {
+    /// Minutes between switching backgrounds
+    pub interval: f64,
+    /// Duration of crossfade between backgrounds in seconds
+    pub crossfade: f64,
+    pub order: SlideshowOrder,
+}
+
+impl Default for SlideshowConfig {
+    fn default() -> Self {
+        Self {
+            interval: 10.0,
+            crossfade: 1.0,
+            order: SlideshowOrder::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlideshowOrder {
+    #[default]
+    Random,
+    /// Alphabetical order of file paths
+    Sorted,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackgroundFit {
+    /// Scale to cover whole monitor, cropping the edges
+    #[default]
+    Cover,
+    /// Scale to fit inside monitor, leaving bars on the sides
+    Contain,
+    /// Stretch to monitor size ignoring aspect ratio
+    Fill,
+    /// Same as `contain`, but never scale up
+    ScaleDown,
+    /// Show in original size at the center
+    Centered,
+    /// Repeat in original size starting from top left corner
+    Tiled,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    /// Never play audio tracks of videos
+    pub mute: bool,
+    /// What to do with videos while running on battery
+    pub on_battery: OnBattery,
+    /// Pause videos while monitor is blanked
+    pub pause_when_blanked: bool,
+    /// Largest width or height videos are decoded at, e.g. `1080`.
+    /// Videos are never decoded larger than needed to cover monitor
+    pub max_size: Option<u32>,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            mute: true,
+            on_battery: OnBattery::default(),
+            pause_when_blanked: true,
+            max_size: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnBattery {
+    #[default]
+    Play,
+    Pause,
+    /// Rewind to the beginning and pause
+    FirstFrame,
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EffectsConfig {
+    /// Radius of blur in pixels
+    pub blur: f64,
+    /// Darken background, from 0 (unchanged) to 1 (black)
+    pub dim: f64,
+    /// Desaturate background, from 0 (unchanged) to 1 (fully gray)
+    pub grayscale: f64,
+    /// Darken edges of background, from 0 (none) to 1 (black corners)
+    pub vignette: f64,
+    /// Color laid over background, strength is set by its alpha,
+    /// e.g. `"rgba(0, 0, 80, 0.3)"`
+    pub tint: Option<String>,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GradientConfig {
+    #[serde(default)]
+    pub kind: GradientKind,
+    /// Direction of linear gradient in degrees, same as in
+    /// CSS `linear-gradient`. `180` goes from top to bottom
+    #[serde(default = "default_gradient_angle")]
+    pub angle: f64,
+    /// Colors spread evenly from start to end
+    pub colors: Vec<String>,
+}
+
+fn default_gradient_angle() -> f64 {
+    180.0
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GradientKind {
+    #[default]
+    Linear,
+    /// Ellipse from center to the farthest corner
+    Radial,
+}
+
+/// `[[background.output]]` rule. Monitor has to match
+/// all of specified fields
+#[derive(Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OutputBackground {
+    /// Connector name, e.g. `"DP-1"`
+    pub connector: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    /// Images, videos, directories or wildcard patterns
+    /// to display on matching monitors
+    #[serde(rename = "path", deserialize_with = "one_or_many")]
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Start fingerprint verification only after device wakes up
     pub await_wakeup: bool,
-    /// Image, video or directory to display on background
+    /// What PAM modules do with credentials such as Kerberos
+    /// tickets or keyrings once session is unlocked
+    pub credentials: CredentialsAction,
+    /// PAM service, i.e. name of file in `/etc/pam.d`
+    pub service: String,
+    /// Service used if `service` is not installed, e.g. `"login"`.
+    /// Without it session is not locked at all, as PAM would
+    /// use `other` service which usually denies everyone
+    pub fallback_service: Option<String>,
+    /// Delays after consecutive failed password or fingerprint attempts
+    pub throttle: ThrottleConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            await_wakeup: false,
+            credentials: CredentialsAction::default(),
+            service: "shackle".to_owned(),
+            fallback_service: None,
+            throttle: ThrottleConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThrottleConfig {
+    /// Consecutive failed attempts allowed without any delay
+    pub free_attempts: u32,
+    /// Seconds to wait after each following failed attempt, the
+    /// last one repeats. Empty to disable throttling
+    pub delays: Vec<f64>,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            delays: vec![1.0, 5.0, 30.0],
+        }
+    }
+}
+
+/// Flag `pam_setcred` is called with after successful authentication
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialsAction {
+    /// Do not call `pam_setcred`
+    Keep,
+    /// `PAM_REFRESH_CRED`, extend lifetime of existing credentials
+    Refresh,
+    /// `PAM_REINITIALIZE_CRED`, obtain them anew, the same
+    /// as display managers do on unlock
+    #[default]
+    Reinitialize,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// Monitor to show password controls on
+    pub controls_monitor: ControlsMonitor,
+    /// Show time and date next to password controls
+    pub clock: Option<ClockConfig>,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    /// Format of time as accepted by `g_date_time_format`, similar
+    /// to strftime. Overrides `twelve_hour` and `seconds`
+    pub format: Option<String>,
+    /// Format of date shown below time, empty to hide date
+    pub date_format: String,
+    /// Use 12-hour time with AM/PM
+    pub twelve_hour: bool,
+    /// Show seconds and update every second rather than every
+    /// minute. Set it if `format` shows seconds
+    pub seconds: bool,
+    /// Where clock is placed relative to password controls
+    pub position: ClockPosition,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            format: None,
+            date_format: DEFAULT_DATE_FORMAT.to_owned(),
+            twelve_hour: false,
+            seconds: false,
+            position: ClockPosition::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockPosition {
+    #[default]
+    Above,
+    Below,
+    Left,
+    Right,
+}
+
+/// Monitor hosting password controls. Other monitors show
+/// only background
+#[derive(Deserialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControlsMonitor {
+    /// First monitor reported by compositor
+    Primary,
+    /// Monitor with given connector name, e.g. `{ connector = "DP-1" }`
+    Connector(String),
+    /// Monitor pointer was last seen on
+    Pointer,
+    /// Monitor that last received keyboard focus
+    #[default]
+    LastFocused,
+}
+
+/// Overrides for variables of built-in stylesheet.
+/// Unset values keep their defaults
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Pango font description, e.g. `"Inter 12"`
+    pub font: Option<String>,
+    /// Aspect ratio of text cursor in entries
+    pub cursor_aspect_ratio: Option<f64>,
+    /// Background color of controls window
+    pub window_background: Option<String>,
+    /// Corner radius of controls window in pixels
+    pub window_radius: Option<f64>,
+    /// Padding of controls window in pixels
+    pub window_padding: Option<f64>,
+    /// Background color of entries and buttons
+    pub element_background: Option<String>,
+    /// Border color of entries
+    pub element_border: Option<String>,
+    /// Corner radius of entries and buttons in pixels
+    pub element_radius: Option<f64>,
+    /// Color of hovered and focused elements
+    pub accent: Option<String>,
+    /// Color of pressed buttons
+    pub accent_active: Option<String>,
+    /// Derive colors of controls from background image, `true`
+    /// by default. Colors set above take precedence
+    pub background_colors: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BehaviourConfig {
+    /// Fork off locker process
+    pub daemonize: bool,
+}
+
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => {
+                write!(f, "Failed to read config {}: {err}", path.display())
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "Invalid config {}:\n{err}", path.display())
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Read config from `path`
     ///
-    /// Currently only .jpg/.jpeg and .mp4 files are supported.
-    /// If path is a directory random supported content will be selected
-    #[arg(short, long)]
-    pub background: Option<PathBuf>,
+    /// Relative paths and paths starting with `~/` inside the file
+    /// are resolved against directory of the file and home directory
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|err| ConfigError::Parse(path.to_owned(), err))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        let background = &mut config.background;
+        let outputs = background.outputs.iter_mut();
+        let output_paths = outputs.flat_map(|output| output.paths.iter_mut());
+        let schedule_paths = background
+            .schedule
+            .iter_mut()
+            .flat_map(|schedule| schedule.day.iter_mut().chain(schedule.night.iter_mut()));
+        for bg in background
+            .paths
+            .iter_mut()
+            .chain(output_paths)
+            .chain(schedule_paths)
+        {
+            *bg = resolve_path(base, bg);
+        }
+
+        Ok(config)
+    }
+
+    /// Override values from file with flags passed on command line
+    fn apply_args(&mut self, args: &Args) {
+        if args.daemonize {
+            self.behaviour.daemonize = true;
+        }
+        if args.await_wakeup {
+            self.auth.await_wakeup = true;
+        }
+        if !args.background.is_empty() {
+            // Background from command line is meant for all monitors
+            self.background.paths = args.background.clone();
+            self.background.outputs.clear();
+            self.background.schedule = None;
+        }
+    }
+}
+
+/// Path of config file. Explicit `--config` is required to exist,
+/// default location is allowed to be absent
+fn config_path(args: &Args) -> Option<PathBuf> {
+    if let Some(path) = &args.config {
+        return Some(path.clone());
+    }
+
+    config_dir()
+        .map(|dir| dir.join("config.toml"))
+        .filter(|path| path.exists())
+}
+
+fn load_config(args: &Args) -> Config {
+    let mut config = match config_path(args) {
+        Some(path) => {
+            info!("Reading config from {}", path.display());
+            Config::from_file(&path).unwrap_or_else(|err| {
+                // Refusing to lock because of a typo is worse
+                // than locking with defaults
+                error!("{err}");
+                error!("Falling back to default configuration");
+                Config::default()
+            })
+        }
+        None => Config::default(),
+    };
+
+    config.apply_args(args);
+    config
+}
+
+/// Accept either a single path or an array of paths
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct OneOrMany;
+
+    impl<'de> Visitor<'de> for OneOrMany {
+        type Value = Vec<PathBuf>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a path or list of paths")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(vec![PathBuf::from(value)])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut paths = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(path) = seq.next_element()? {
+                paths.push(path);
+            }
+            Ok(paths)
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany)
+}
+
+fn resolve_path(base: &Path, path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        if let Some(home) = home::home_dir() {
+            return home.join(rest);
+        }
+    }
+
+    base.join(path)
 }
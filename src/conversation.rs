@@ -0,0 +1,312 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk4_session_lock::Instance as SessionLockInstance;
+use log::error;
+
+use crate::auth::pam::{authenticate, Failure, Message};
+use crate::auth::throttle::{connect_throttled, record_failure, remaining};
+use crate::config::config;
+
+/// Shown in entry until PAM asks its first question
+const DEFAULT_PROMPT: &str = "Password";
+/// Shown if PAM failed without explaining why
+const FAILURE_MESSAGE: &str = "Authentication failed";
+
+/// Widgets PAM conversation is held in
+struct Conversation {
+    entry: gtk::Entry,
+    button: gtk::Button,
+    message: gtk::Label,
+    /// Time until attempts are allowed again
+    countdown: gtk::Label,
+    /// Receives text of entry once user submits it
+    answer: RefCell<Option<oneshot::Sender<String>>>,
+    /// Whether PAM reported an error during current attempt
+    error_shown: Cell<bool>,
+    /// Whether controls are disabled until countdown ends
+    throttled: Cell<bool>,
+}
+
+impl Conversation {
+    /// Run PAM authentication to the end, showing
+    /// its questions and messages
+    async fn authenticate(&self) -> bool {
+        let (messages, mut received) = mpsc::unbounded();
+        let (result, finished) = oneshot::channel();
+        let credentials = config().auth.credentials;
+        // PAM conversation blocks until user answers, which
+        // may take forever, so it is not run in a thread pool
+        let spawned = thread::Builder::new()
+            .name("pam".to_owned())
+            .spawn(move || {
+                let _ = result.send(authenticate(messages, credentials));
+            });
+        if let Err(err) = spawned {
+            error!("Failed to start authentication: {err}");
+            self.show_message(FAILURE_MESSAGE, true);
+            // Returning right away would spin main loop,
+            // starving fingerprint and signal unlock
+            self.wait_retry().await;
+            return false;
+        }
+
+        self.error_shown.set(false);
+        let mut asked = false;
+
+        while let Some(message) = received.next().await {
+            match message {
+                Message::Prompt { text, echo, answer } => {
+                    asked = true;
+                    self.answer.replace(Some(answer));
+                    self.show_prompt(&text, echo);
+                }
+                Message::Info(text) => self.show_message(&text, false),
+                Message::Error(text) => self.show_message(&text, true),
+            }
+        }
+        // Messages end once PAM thread finishes
+        self.answer.take();
+
+        let failure = match finished.await {
+            Ok(Ok(())) => return true,
+            Ok(Err(failure)) => failure,
+            // PAM thread panicked
+            Err(_) => Failure::Unavailable,
+        };
+
+        if !self.error_shown.get() {
+            self.show_message(FAILURE_MESSAGE, true);
+        }
+        match failure {
+            // Only wrong answers count as attempts, expired or
+            // locked account of authenticated user is no guess
+            Failure::Authentication if asked => record_failure(),
+            Failure::Account if asked => (),
+            // PAM failed before asking anything
+            _ => {
+                // Nothing user could do differently, so do
+                // not start over until they ask to
+                self.wait_retry().await;
+            }
+        }
+
+        false
+    }
+
+    async fn wait_retry(&self) {
+        let (answer, answered) = oneshot::channel();
+        self.answer.replace(Some(answer));
+        self.show_prompt("Press Enter to try again", true);
+        let _ = answered.await;
+    }
+
+    fn show_prompt(&self, text: &str, echo: bool) {
+        let text = text.trim().trim_end_matches(':').trim_end();
+        let text = if text.is_empty() {
+            DEFAULT_PROMPT
+        } else {
+            text
+        };
+
+        self.entry.set_placeholder_text(Some(text));
+        self.entry.set_visibility(echo);
+        if echo {
+            self.entry.set_input_purpose(gtk::InputPurpose::FreeForm);
+            self.entry.remove_css_class("password");
+        } else {
+            self.entry.set_input_purpose(gtk::InputPurpose::Password);
+            self.entry.add_css_class("password");
+        }
+
+        self.set_waiting(false);
+    }
+
+    /// Hand text of entry to pending question
+    fn submit(&self) {
+        let Some(answer) = self.answer.take() else {
+            return;
+        };
+
+        let text = self.entry.text().to_string();
+        // Answer is not kept in widget longer than needed
+        self.entry.set_text("");
+        self.set_waiting(true);
+        self.clear_message();
+
+        let _ = answer.send(text);
+    }
+
+    /// Blank out controls to show that auth is in progress.
+    /// They are kept blank while attempts are throttled
+    fn set_waiting(&self, waiting: bool) {
+        let active = !waiting && !self.throttled.get();
+        self.entry.set_sensitive(active);
+        self.button.set_sensitive(active);
+        if active {
+            self.entry.grab_focus();
+        }
+    }
+
+    /// Disable controls and show time left until
+    /// attempts are allowed again
+    fn start_countdown(self: &Rc<Self>) {
+        if self.throttled.replace(true) {
+            // Running countdown picks up new delay by itself
+            return;
+        }
+        self.set_waiting(true);
+
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to = conversation)]
+            self,
+            async move {
+                while let Some(remaining) = remaining() {
+                    conversation.countdown.set_label(&countdown_text(remaining));
+                    conversation.countdown.set_visible(true);
+
+                    // Wake up on whole seconds so that
+                    // shown number decreases evenly
+                    let tick = match remaining.subsec_nanos() {
+                        0 => Duration::from_secs(1),
+                        nanos => Duration::from_nanos(nanos.into()),
+                    };
+                    glib::timeout_future(tick).await;
+                }
+
+                conversation.countdown.set_visible(false);
+                conversation.throttled.set(false);
+                // Question may have been asked during countdown
+                let waiting = conversation.answer.borrow().is_none();
+                conversation.set_waiting(waiting);
+            }
+        ));
+    }
+
+    /// Messages are collected until user answers, PAM
+    /// may explain failure in several of them
+    fn show_message(&self, text: &str, error: bool) {
+        let text = text.trim();
+        if self.message.is_visible() {
+            self.message
+                .set_label(&format!("{}\n{text}", self.message.label()));
+        } else {
+            self.message.set_label(text);
+        }
+        self.message.set_visible(true);
+        if error {
+            self.error_shown.set(true);
+            self.message.add_css_class("error");
+        }
+    }
+
+    fn clear_message(&self) {
+        self.message.set_label("");
+        self.message.set_visible(false);
+        self.message.remove_css_class("error");
+    }
+}
+
+fn countdown_text(remaining: Duration) -> String {
+    // Rounded up, so that zero is never shown
+    let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    let time = match seconds {
+        0..=1 => "1 second".to_owned(),
+        2..=59 => format!("{seconds} seconds"),
+        _ => format!("{}:{:02}", seconds / 60, seconds % 60),
+    };
+
+    format!("Too many failed attempts\nTry again in {time}")
+}
+
+/// Entry for answers to PAM questions, messages of PAM
+/// and unlock button. Session is unlocked once PAM
+/// authentication succeeds, failed one is started over
+pub fn conversation(lock: &SessionLockInstance) -> gtk::Widget {
+    let bbox = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(24)
+        .build();
+
+    let entry = gtk::Entry::builder()
+        .placeholder_text(DEFAULT_PROMPT)
+        .visibility(false)
+        .input_purpose(gtk::InputPurpose::Password)
+        .css_classes(["password"])
+        .sensitive(false)
+        .build();
+    let message = gtk::Label::builder()
+        .css_classes(["auth-message"])
+        .wrap(true)
+        .justify(gtk::Justification::Center)
+        .visible(false)
+        .build();
+    let countdown = gtk::Label::builder()
+        .css_classes(["auth-countdown"])
+        .justify(gtk::Justification::Center)
+        .visible(false)
+        .build();
+    let button = gtk::Button::builder()
+        .label("Unlock")
+        .sensitive(false)
+        .build();
+
+    // Controls may move between monitors, map is
+    // emitted every time they are attached to a new one
+    entry.connect_map(|entry| {
+        entry.grab_focus();
+    });
+
+    bbox.append(&entry);
+    bbox.append(&message);
+    bbox.append(&countdown);
+    bbox.append(&button);
+
+    let conversation = Rc::new(Conversation {
+        entry: entry.clone(),
+        button: button.clone(),
+        message,
+        countdown,
+        answer: RefCell::new(None),
+        error_shown: Cell::new(false),
+        throttled: Cell::new(false),
+    });
+
+    entry.connect_activate(clone!(
+        #[weak]
+        conversation,
+        move |_| conversation.submit()
+    ));
+    button.connect_clicked(clone!(
+        #[weak]
+        conversation,
+        move |_| conversation.submit()
+    ));
+
+    connect_throttled(clone!(
+        #[weak]
+        conversation,
+        move || conversation.start_countdown()
+    ));
+    // Attempts may still be refused since before locker restarted
+    if remaining().is_some() {
+        conversation.start_countdown();
+    }
+
+    glib::spawn_future_local(clone!(
+        #[weak]
+        lock,
+        async move {
+            while !conversation.authenticate().await {}
+            lock.unlock();
+        }
+    ));
+
+    bbox.into()
+}
@@ -0,0 +1,26 @@
+use std::{env, path::PathBuf};
+
+/// Resolve XDG base directory from `var`, falling back
+/// to `fallback` relative to home directory if it is unset
+/// or not absolute as required by the specification
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home::home_dir().map(|home| home.join(fallback)))
+}
+
+/// `$XDG_CONFIG_HOME/shackle`
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("shackle"))
+}
+
+/// `$XDG_STATE_HOME/shackle`
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state").map(|dir| dir.join("shackle"))
+}
+
+/// `$XDG_CACHE_HOME/shackle`
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache").map(|dir| dir.join("shackle"))
+}
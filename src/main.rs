@@ -1,13 +1,21 @@
 mod auth;
+mod background;
+mod clock;
 mod config;
+mod conversation;
+mod dirs;
 mod instance;
+mod reload;
+mod style;
+mod surfaces;
 mod ui;
+mod user;
 
 use std::rc::Rc;
 
 use fork::daemon;
 use fork::Fork;
-use gtk::gdk;
+use gtk::gio;
 use gtk::glib::{self, clone};
 use gtk::prelude::*;
 use gtk4_session_lock::Instance as SessionLockInstance;
@@ -15,13 +23,17 @@ use log::warn;
 use log::{error, info};
 
 use crate::auth::fprint::check_fingerprint;
+use crate::auth::pam::{check_account, select_service};
 use crate::auth::signal::wait_signal;
+use crate::auth::throttle::reset_failures;
+use crate::background::capture_screenshots;
 use crate::config::config;
 use crate::instance::lock_sole_instance;
 use crate::instance::AppHold;
-use crate::ui::background;
+use crate::reload::watch_config;
+use crate::style::load_css;
+use crate::surfaces::Surfaces;
 use crate::ui::controls;
-use crate::ui::load_css;
 use crate::ui::set_gtk_settings;
 
 fn on_session_locked(_: &SessionLockInstance) {
@@ -35,28 +47,17 @@ fn on_session_lock_failed(app: &gtk::Application) {
 
 fn on_session_unlocked(app: &gtk::Application, hold: &AppHold) {
     info!("Session unlocked");
+    reset_failures();
     hold.release();
     app.quit();
 }
 
-fn on_monitor_present(lock: &SessionLockInstance, monitor: gdk::Monitor, app: &gtk::Application) {
-    // TODO: this function creates ui on each monitor. We need to present controls only on one
-    // and just beatuiful background on rest
-
-    let window = gtk::ApplicationWindow::new(app);
-
-    let bg_overlay = gtk::Overlay::new();
-    bg_overlay.set_child(Some(&background()));
-    bg_overlay.add_overlay(&controls(lock));
-
-    window.set_child(Some(&bg_overlay));
-
-    lock.assign_window_to_monitor(&window, &monitor);
-    // No need for window.present
-    // gtk_session_lock_instance_assign_window_to_monitor() does that
-}
-
 fn activate(app: &gtk::Application) {
+    // Requested before locking, screenshots are shown once ready
+    if let Some(screenshot) = &config().background.screenshot {
+        capture_screenshots(screenshot);
+    }
+
     let lock = SessionLockInstance::new();
     lock.connect_locked(on_session_locked);
     lock.connect_failed(clone!(
@@ -78,17 +79,29 @@ fn activate(app: &gtk::Application) {
         move |_| on_session_unlocked(&app, &hold)
     ));
 
+    // Controls are created once and moved between monitors
+    let surfaces = Surfaces::new(controls(&lock));
+
     lock.connect_monitor(clone!(
         #[weak]
         app,
-        move |lock, monitor| on_monitor_present(&lock, monitor.clone(), &app)
+        #[strong]
+        surfaces,
+        move |lock, monitor| surfaces.add(lock, monitor.clone(), &app)
     ));
 
     glib::spawn_future_local(clone!(
         #[weak]
         lock,
         async move {
-            if check_fingerprint(config().await_wakeup).await {
+            if !check_fingerprint(config().auth.await_wakeup).await {
+                return;
+            }
+            // Fingerprint only proves identity, account
+            // may still be expired or locked
+            let credentials = config().auth.credentials;
+            let checked = gio::spawn_blocking(move || check_account(credentials)).await;
+            if let Ok(Ok(())) = checked {
                 lock.unlock();
             }
         }
@@ -103,12 +116,23 @@ fn activate(app: &gtk::Application) {
         }
     ));
 
+    watch_config(&surfaces);
+
     // When this function exits session is not guaranteed to be locked
     lock.lock();
 }
 
 fn main() {
-    if config().daemonize {
+    env_logger::init();
+
+    // Checked before forking so that error reaches terminal
+    if select_service(&config().auth).is_none() {
+        error!("Refusing to lock session as it could not be unlocked");
+        error!("Install PAM service file or set auth.fallback_service, e.g. to \"login\"");
+        std::process::exit(1);
+    }
+
+    if config().behaviour.daemonize {
         if let Ok(Fork::Child) = daemon(true, true) {
             start();
         }
@@ -118,8 +142,6 @@ fn main() {
 }
 
 fn start() {
-    env_logger::init();
-
     let Some(_lock) = lock_sole_instance() else {
         warn!("Another instance of shackle is running. Terminating");
         return;
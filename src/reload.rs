@@ -0,0 +1,157 @@
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gtk::gio;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use log::{error, info, warn};
+
+use crate::config::{config, config_file, reload_config};
+use crate::style::{reload_css, user_stylesheet_files};
+use crate::surfaces::Surfaces;
+use crate::ui::set_gtk_settings;
+
+/// Editors tend to touch file several times during
+/// a single save, apply changes only once they settle down
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+/// What has to be reloaded, later variants include earlier ones
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    /// User stylesheet changed, nothing else depends on it
+    Stylesheet,
+    Config,
+}
+
+thread_local! {
+    /// File monitors stop reporting events once dropped
+    static MONITORS: RefCell<Vec<gio::FileMonitor>> = const { RefCell::new(Vec::new()) };
+    static RELOAD_PENDING: Cell<Option<Change>> = const { Cell::new(None) };
+}
+
+/// Apply changes to configuration without unlocking
+///
+/// Reload is triggered when config file or user stylesheet
+/// changes on disk or SIGHUP is received
+pub fn watch_config(surfaces: &Rc<Surfaces>) {
+    if let Some(path) = config_file() {
+        watch_file(surfaces, &path, Change::Config);
+    }
+    for path in user_stylesheet_files() {
+        watch_file(surfaces, &path, Change::Stylesheet);
+    }
+
+    glib::unix_signal_add_local(
+        nix::sys::signal::Signal::SIGHUP as i32,
+        clone!(
+            #[weak]
+            surfaces,
+            #[upgrade_or]
+            glib::ControlFlow::Continue,
+            move || {
+                info!("Recieved SIGHUP.");
+                reload(&surfaces, Change::Config);
+                glib::ControlFlow::Continue
+            }
+        ),
+    );
+}
+
+fn watch_file(surfaces: &Rc<Surfaces>, path: &Path, change: Change) {
+    // File is allowed to not exist yet, monitor
+    // will report when it is created
+    let monitor = match gio::File::for_path(path)
+        .monitor_file(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+    {
+        Ok(monitor) => monitor,
+        Err(err) => {
+            warn!("Failed to watch {}: {err}", path.display());
+            return;
+        }
+    };
+
+    monitor.connect_changed(clone!(
+        #[weak]
+        surfaces,
+        move |_, _, _, event| {
+            // Plain `Changed` is reported for every write and
+            // is followed by `ChangesDoneHint` anyway
+            if !matches!(
+                event,
+                gio::FileMonitorEvent::Changed
+                    | gio::FileMonitorEvent::AttributeChanged
+                    | gio::FileMonitorEvent::PreUnmount
+                    | gio::FileMonitorEvent::Unmounted
+            ) {
+                schedule_reload(&surfaces, change);
+            }
+        }
+    ));
+
+    info!("Watching {} for changes", path.display());
+    MONITORS.with_borrow_mut(|monitors| monitors.push(monitor));
+}
+
+fn schedule_reload(surfaces: &Rc<Surfaces>, change: Change) {
+    let pending = RELOAD_PENDING.get();
+    RELOAD_PENDING.set(pending.max(Some(change)));
+    if pending.is_some() {
+        return;
+    }
+
+    glib::timeout_add_local_once(
+        RELOAD_DELAY,
+        clone!(
+            #[weak]
+            surfaces,
+            move || {
+                if let Some(change) = RELOAD_PENDING.take() {
+                    reload(&surfaces, change);
+                }
+            }
+        ),
+    );
+}
+
+fn reload(surfaces: &Surfaces, change: Change) {
+    if change == Change::Stylesheet {
+        reload_css();
+        return;
+    }
+
+    let previous = config();
+    let reloaded = match reload_config() {
+        Ok(()) => {
+            info!("Configuration reloaded");
+            true
+        }
+        Err(err) => {
+            error!("{err}");
+            error!("Keeping previous configuration");
+            false
+        }
+    };
+
+    // User stylesheet does not depend on configuration,
+    // reload it even if config is broken
+    reload_css();
+
+    if !reloaded {
+        return;
+    }
+
+    set_gtk_settings();
+
+    // Rebuilding backgrounds restarts videos,
+    // so it is done only if needed
+    let current = config();
+    if current.background != previous.background {
+        surfaces.reload_backgrounds();
+    }
+    if current.ui.clock != previous.ui.clock {
+        surfaces.reload_clock();
+    }
+    surfaces.place_controls();
+}
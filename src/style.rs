@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use gtk::gdk;
+use gtk::glib;
+use gtk::prelude::*;
+use log::{error, info, warn};
+
+use crate::background::Palette;
+use crate::config::{config, ThemeConfig};
+use crate::dirs::config_dir;
+
+/// Built-in stylesheet compiled with default theme variables
+const CSS_SOURCE: &str = include_str!(concat!(env!("OUT_DIR"), "/style.css"));
+/// Source of built-in stylesheet, compiled at startup
+/// if theme variables are overridden in config
+const SCSS_SOURCE: &str = include_str!("style/main.scss");
+
+/// Text color used with colors derived from background
+const TEXT_COLOR: gdk::RGBA = gdk::RGBA::WHITE;
+/// WCAG AA contrast of regular text against its background
+const MIN_TEXT_CONTRAST: f64 = 4.5;
+/// WCAG contrast of interface elements against adjacent colors
+const MIN_ELEMENT_CONTRAST: f64 = 3.0;
+
+/// Providers installed by [`load_css`]
+struct Providers {
+    theme: gtk::CssProvider,
+    user: gtk::CssProvider,
+}
+
+/// Backgrounds theme colors may be derived from
+#[derive(Default)]
+struct BackgroundColors {
+    palettes: HashMap<gdk::Monitor, Palette>,
+    /// Monitor showing controls, its background is used
+    controls: Option<gdk::Monitor>,
+}
+
+impl BackgroundColors {
+    fn palette(&self) -> Option<Palette> {
+        self.controls
+            .as_ref()
+            .and_then(|monitor| self.palettes.get(monitor))
+            .copied()
+    }
+}
+
+thread_local! {
+    /// Kept to reload styles in place
+    static CSS_PROVIDERS: RefCell<Option<Providers>> = const { RefCell::new(None) };
+    static BACKGROUND_COLORS: RefCell<BackgroundColors> = RefCell::new(BackgroundColors::default());
+}
+
+/// Install built-in stylesheet and user stylesheet on top of it
+pub fn load_css() {
+    let Some(display) = gdk::Display::default() else {
+        error!("Failed to load css, could not get gdk::Display");
+        return;
+    };
+
+    let css = gtk::CssProvider::new();
+    css.load_from_string(&theme_stylesheet(&config().theme, background_palette()));
+    gtk::style_context_add_provider_for_display(
+        &display,
+        &css,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
+
+    let user_css = gtk::CssProvider::new();
+    user_css.connect_parsing_error(log_parsing_error);
+    load_user_stylesheet(&user_css);
+    gtk::style_context_add_provider_for_display(
+        &display,
+        &user_css,
+        gtk::STYLE_PROVIDER_PRIORITY_USER,
+    );
+    CSS_PROVIDERS.replace(Some(Providers {
+        theme: css,
+        user: user_css,
+    }));
+}
+
+/// Regenerate theme and reload user stylesheet
+/// into providers created by [`load_css`]
+pub fn reload_css() {
+    let providers = CSS_PROVIDERS.with_borrow(|providers| {
+        providers
+            .as_ref()
+            .map(|providers| (providers.theme.clone(), providers.user.clone()))
+    });
+
+    match providers {
+        Some((theme, user)) => {
+            theme.load_from_string(&theme_stylesheet(&config().theme, background_palette()));
+            load_user_stylesheet(&user);
+        }
+        None => load_css(),
+    }
+}
+
+/// Record colors of background shown on `monitor`,
+/// [`None`] if they are unknown
+pub fn set_background_palette(monitor: &gdk::Monitor, palette: Option<Palette>) {
+    update_background_colors(|colors| match palette {
+        Some(palette) => {
+            colors.palettes.insert(monitor.clone(), palette);
+        }
+        None => {
+            colors.palettes.remove(monitor);
+        }
+    });
+}
+
+/// Derive theme colors from background of `monitor`
+pub fn set_controls_monitor(monitor: &gdk::Monitor) {
+    update_background_colors(|colors| colors.controls = Some(monitor.clone()));
+}
+
+/// Regenerate theme if palette in use changed
+fn update_background_colors(update: impl FnOnce(&mut BackgroundColors)) {
+    let (previous, current) = BACKGROUND_COLORS.with_borrow_mut(|colors| {
+        let previous = colors.palette();
+        update(colors);
+        (previous, colors.palette())
+    });
+
+    let theme = CSS_PROVIDERS
+        .with_borrow(|providers| providers.as_ref().map(|providers| providers.theme.clone()));
+    let config = config();
+    if previous != current && config.theme.background_colors.unwrap_or(true) {
+        if let Some(theme) = theme {
+            theme.load_from_string(&theme_stylesheet(&config.theme, current));
+        }
+    }
+}
+
+fn background_palette() -> Option<Palette> {
+    BACKGROUND_COLORS.with_borrow(BackgroundColors::palette)
+}
+
+/// Possible locations of user stylesheet in order of preference.
+/// Files are not guaranteed to exist
+pub fn user_stylesheet_files() -> Vec<PathBuf> {
+    let Some(dir) = config_dir() else {
+        return Vec::new();
+    };
+
+    vec![dir.join("style.scss"), dir.join("style.css")]
+}
+
+fn load_user_stylesheet(provider: &gtk::CssProvider) {
+    let Some(path) = user_stylesheet_files()
+        .into_iter()
+        .find(|path| path.exists())
+    else {
+        provider.load_from_string("");
+        return;
+    };
+
+    info!("Loading user stylesheet {}", path.display());
+
+    if path.extension().is_some_and(|ext| ext == "css") {
+        provider.load_from_path(&path);
+        return;
+    }
+
+    match compile_scss(&path) {
+        Ok(css) => provider.load_from_string(&css),
+        Err(err) => {
+            error!("Failed to compile {}:\n{err}", path.display());
+            error!("Using built-in stylesheet");
+            provider.load_from_string("");
+        }
+    }
+}
+
+/// Built-in stylesheet with variables from `[theme]` applied
+/// and colors derived from `palette` of background
+///
+/// Falls back to precompiled stylesheet if nothing is
+/// overridden or overrides fail to compile
+fn theme_stylesheet(theme: &ThemeConfig, palette: Option<Palette>) -> String {
+    let palette = palette.filter(|_| theme.background_colors.unwrap_or(true));
+    let variables = palette.map(palette_variables).unwrap_or_default() + &theme_variables(theme);
+    if variables.is_empty() {
+        return CSS_SOURCE.to_owned();
+    }
+
+    let options = grass::Options::default().style(grass::OutputStyle::Expanded);
+    match grass::from_string(variables + SCSS_SOURCE, &options) {
+        Ok(css) => css,
+        Err(err) => {
+            error!("Failed to apply theme:\n{err}");
+            error!("Using default theme");
+            CSS_SOURCE.to_owned()
+        }
+    }
+}
+
+/// SCSS declarations of colors matching `palette`
+///
+/// Backgrounds of text are darkened until white text on
+/// them is legible. Colors set in `[theme]` are declared
+/// after these and take precedence
+fn palette_variables(palette: Palette) -> String {
+    let window = legible(
+        mix(palette.dominant, gdk::RGBA::BLACK, 0.7),
+        MIN_TEXT_CONTRAST,
+    );
+    let element = legible(
+        mix(palette.dominant, gdk::RGBA::BLACK, 0.55),
+        MIN_TEXT_CONTRAST,
+    );
+    let hover = legible(
+        mix(palette.accent, gdk::RGBA::BLACK, 0.3),
+        MIN_TEXT_CONTRAST,
+    );
+    let active = legible(palette.accent, MIN_ELEMENT_CONTRAST);
+
+    // Border of entries must stand out from window
+    let mut border = mix(palette.accent, palette.dominant, 0.5);
+    while contrast(border, window) < MIN_ELEMENT_CONTRAST && luminance(border) < 1.0 {
+        border = mix(border, gdk::RGBA::WHITE, 0.1);
+    }
+
+    let colors = [
+        ("fg-text", TEXT_COLOR),
+        ("bg-window", window),
+        ("el-neutral", element),
+        ("el-border", border),
+        ("el-hover", hover),
+        ("el-active", active),
+    ];
+
+    let mut variables = String::new();
+    for (variable, color) in colors {
+        let _ = writeln!(variables, "${variable}: {color};");
+    }
+
+    variables
+}
+
+/// `color` darkened until text has at least `min_contrast` on it
+fn legible(mut color: gdk::RGBA, min_contrast: f64) -> gdk::RGBA {
+    while contrast(TEXT_COLOR, color) < min_contrast && luminance(color) > 0.0 {
+        color = mix(color, gdk::RGBA::BLACK, 0.1);
+    }
+
+    color
+}
+
+/// `amount` of `other` mixed into `color`
+fn mix(color: gdk::RGBA, other: gdk::RGBA, amount: f32) -> gdk::RGBA {
+    let channel = |a: f32, b: f32| a + (b - a) * amount;
+
+    gdk::RGBA::new(
+        channel(color.red(), other.red()),
+        channel(color.green(), other.green()),
+        channel(color.blue(), other.blue()),
+        1.0,
+    )
+}
+
+/// WCAG contrast ratio, from 1 to 21
+fn contrast(a: gdk::RGBA, b: gdk::RGBA) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// WCAG relative luminance of sRGB color
+fn luminance(color: gdk::RGBA) -> f64 {
+    let linear = |channel: f32| {
+        let channel = channel as f64;
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    0.2126 * linear(color.red()) + 0.7152 * linear(color.green()) + 0.0722 * linear(color.blue())
+}
+
+/// SCSS declarations overriding `!default` variables of `main.scss`
+fn theme_variables(theme: &ThemeConfig) -> String {
+    let colors = [
+        ("window_background", "bg-window", &theme.window_background),
+        (
+            "element_background",
+            "el-neutral",
+            &theme.element_background,
+        ),
+        ("element_border", "el-border", &theme.element_border),
+        ("accent", "el-hover", &theme.accent),
+        ("accent_active", "el-active", &theme.accent_active),
+    ];
+    let lengths = [
+        ("window_radius", "window-radius", theme.window_radius),
+        ("window_padding", "window-padding", theme.window_padding),
+        ("element_radius", "el-radius", theme.element_radius),
+    ];
+
+    let mut variables = String::new();
+
+    for (key, variable, value) in colors {
+        let Some(value) = value else {
+            continue;
+        };
+
+        // Anything GTK understands as a color is valid SCSS as well
+        if gdk::RGBA::parse(value.as_str()).is_ok() {
+            let _ = writeln!(variables, "${variable}: {value};");
+        } else {
+            warn!("Ignoring theme.{key}: \"{value}\" is not a valid color");
+        }
+    }
+
+    for (key, variable, value) in lengths {
+        let Some(value) = value else {
+            continue;
+        };
+
+        if value.is_finite() && value >= 0.0 {
+            let _ = writeln!(variables, "${variable}: {value}px;");
+        } else {
+            warn!("Ignoring theme.{key}: {value} is not a valid length");
+        }
+    }
+
+    variables
+}
+
+fn compile_scss(path: &Path) -> Result<String, Box<grass::Error>> {
+    let mut options = grass::Options::default().style(grass::OutputStyle::Expanded);
+    // Allow @use and @import relative to the stylesheet
+    if let Some(dir) = path.parent() {
+        options = options.load_path(dir);
+    }
+
+    grass::from_path(path, &options)
+}
+
+fn log_parsing_error(_: &gtk::CssProvider, section: &gtk::CssSection, err: &glib::Error) {
+    let location = section.start_location();
+    let file = section
+        .file()
+        .and_then(|file| file.path())
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "<stylesheet>".to_owned());
+
+    // GTK counts lines and characters from zero
+    warn!(
+        "{file}:{}:{}: {err}",
+        location.lines() + 1,
+        location.line_chars() + 1
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legible_colors_meet_contrast() {
+        let colors = [
+            gdk::RGBA::WHITE,
+            gdk::RGBA::new(1.0, 0.9, 0.2, 1.0),
+            gdk::RGBA::new(0.5, 0.7, 1.0, 1.0),
+            gdk::RGBA::new(0.6, 0.6, 0.6, 1.0),
+        ];
+
+        for color in colors {
+            for min_contrast in [MIN_TEXT_CONTRAST, MIN_ELEMENT_CONTRAST] {
+                let legible = legible(color, min_contrast);
+                assert!(
+                    contrast(TEXT_COLOR, legible) >= min_contrast,
+                    "{color} darkened to {legible} for {min_contrast}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dark_colors_are_kept() {
+        let color = gdk::RGBA::new(0.1, 0.2, 0.4, 1.0);
+        assert_eq!(legible(color, MIN_TEXT_CONTRAST), color);
+    }
+
+    #[test]
+    fn contrast_matches_wcag() {
+        assert!((contrast(gdk::RGBA::WHITE, gdk::RGBA::BLACK) - 21.0).abs() < 1e-9);
+        assert!((contrast(gdk::RGBA::BLACK, gdk::RGBA::BLACK) - 1.0).abs() < 1e-9);
+        // #767676 is the lightest gray white text passes AA on
+        let gray = |value: f32| gdk::RGBA::new(value, value, value, 1.0);
+        assert!(contrast(TEXT_COLOR, gray(0x76 as f32 / 255.0)) >= MIN_TEXT_CONTRAST);
+        assert!(contrast(TEXT_COLOR, gray(0x77 as f32 / 255.0)) < MIN_TEXT_CONTRAST);
+    }
+}
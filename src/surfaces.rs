@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gtk::gdk;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk4_session_lock::Instance as SessionLockInstance;
+use log::info;
+
+use crate::background::background;
+use crate::config::{config, ControlsMonitor};
+use crate::style::{set_background_palette, set_controls_monitor};
+use crate::ui::reload_clock;
+
+/// Lock surface presented on a single monitor
+struct Surface {
+    monitor: gdk::Monitor,
+    window: gtk::ApplicationWindow,
+    overlay: gtk::Overlay,
+}
+
+/// Lock surfaces of all monitors
+///
+/// Only one surface hosts controls, the rest show just
+/// the background. Controls are moved between surfaces
+/// according to [`ControlsMonitor`] policy
+pub struct Surfaces {
+    surfaces: RefCell<Vec<Surface>>,
+    controls: gtk::Widget,
+    last_pointer: RefCell<Option<gdk::Monitor>>,
+    last_focused: RefCell<Option<gdk::Monitor>>,
+}
+
+impl Surfaces {
+    pub fn new(controls: gtk::Widget) -> Rc<Self> {
+        Rc::new(Self {
+            surfaces: RefCell::new(Vec::new()),
+            controls,
+            last_pointer: RefCell::new(None),
+            last_focused: RefCell::new(None),
+        })
+    }
+
+    /// Create lock surface for newly present monitor
+    pub fn add(
+        self: &Rc<Self>,
+        lock: &SessionLockInstance,
+        monitor: gdk::Monitor,
+        app: &gtk::Application,
+    ) {
+        let window = gtk::ApplicationWindow::new(app);
+
+        let overlay = gtk::Overlay::new();
+        overlay.set_child(Some(&background(&monitor)));
+        window.set_child(Some(&overlay));
+
+        let motion = gtk::EventControllerMotion::new();
+        motion.connect_enter(clone!(
+            #[weak(rename_to = surfaces)]
+            self,
+            #[strong]
+            monitor,
+            move |_, _, _| {
+                surfaces.last_pointer.replace(Some(monitor.clone()));
+                surfaces.place_controls();
+            }
+        ));
+        window.add_controller(motion);
+
+        window.connect_is_active_notify(clone!(
+            #[weak(rename_to = surfaces)]
+            self,
+            #[strong]
+            monitor,
+            move |window| {
+                if window.is_active() {
+                    surfaces.last_focused.replace(Some(monitor.clone()));
+                    surfaces.place_controls();
+                }
+            }
+        ));
+
+        monitor.connect_invalidate(clone!(
+            #[weak(rename_to = surfaces)]
+            self,
+            move |monitor| surfaces.remove(monitor)
+        ));
+
+        self.surfaces.borrow_mut().push(Surface {
+            monitor: monitor.clone(),
+            window: window.clone(),
+            overlay,
+        });
+        self.place_controls();
+
+        lock.assign_window_to_monitor(&window, &monitor);
+        // No need for window.present
+        // gtk_session_lock_instance_assign_window_to_monitor() does that
+    }
+
+    /// Recreate clock shown next to controls
+    pub fn reload_clock(&self) {
+        reload_clock(&self.controls);
+    }
+
+    /// Recreate backgrounds of all monitors
+    pub fn reload_backgrounds(&self) {
+        for surface in self.surfaces.borrow().iter() {
+            surface
+                .overlay
+                .set_child(Some(&background(&surface.monitor)));
+        }
+    }
+
+    fn remove(&self, monitor: &gdk::Monitor) {
+        let removed = {
+            let mut surfaces = self.surfaces.borrow_mut();
+            surfaces
+                .iter()
+                .position(|surface| &surface.monitor == monitor)
+                .map(|idx| surfaces.remove(idx))
+        };
+        let Some(surface) = removed else {
+            return;
+        };
+
+        info!("Monitor {} disconnected", monitor_name(monitor));
+        set_background_palette(monitor, None);
+
+        if self.controls.parent().as_ref() == Some(surface.overlay.upcast_ref::<gtk::Widget>()) {
+            surface.overlay.remove_overlay(&self.controls);
+        }
+        surface.window.destroy();
+
+        self.place_controls();
+    }
+
+    /// Move controls to monitor selected by policy
+    /// if they are not there yet
+    pub fn place_controls(&self) {
+        // Do not hold the borrow while widgets are being
+        // moved, that may cause signal handlers to run
+        let Some((monitor, overlay)) = self
+            .controls_target(&self.surfaces.borrow())
+            .map(|target| (target.monitor.clone(), target.overlay.clone()))
+        else {
+            return;
+        };
+        set_controls_monitor(&monitor);
+
+        if self.controls.parent().as_ref() == Some(overlay.upcast_ref::<gtk::Widget>()) {
+            return;
+        }
+
+        if let Some(previous) = self.controls.parent().and_downcast::<gtk::Overlay>() {
+            previous.remove_overlay(&self.controls);
+        }
+        overlay.add_overlay(&self.controls);
+
+        info!("Showing controls on {}", monitor_name(&monitor));
+    }
+
+    fn controls_target<'a>(&self, surfaces: &'a [Surface]) -> Option<&'a Surface> {
+        let by_monitor = |monitor: Option<gdk::Monitor>| {
+            monitor.and_then(|monitor| surfaces.iter().find(|surface| surface.monitor == monitor))
+        };
+
+        let preferred = match &config().ui.controls_monitor {
+            ControlsMonitor::Primary => None,
+            ControlsMonitor::Connector(connector) => surfaces
+                .iter()
+                .find(|surface| surface.monitor.connector().as_deref() == Some(connector.as_str())),
+            ControlsMonitor::Pointer => by_monitor(self.last_pointer.borrow().clone()),
+            ControlsMonitor::LastFocused => by_monitor(self.last_focused.borrow().clone()),
+        };
+
+        preferred.or_else(|| primary(surfaces))
+    }
+}
+
+/// Wayland has no notion of primary output, so the first
+/// monitor in order reported by compositor is used instead
+fn primary(surfaces: &[Surface]) -> Option<&Surface> {
+    let monitors = gdk::Display::default().map(|display| display.monitors());
+
+    let first = monitors
+        .iter()
+        .flat_map(|monitors| monitors.iter::<gdk::Monitor>().flatten())
+        .find_map(|monitor| surfaces.iter().find(|surface| surface.monitor == monitor));
+    first.or_else(|| surfaces.first())
+}
+
+fn monitor_name(monitor: &gdk::Monitor) -> String {
+    monitor
+        .connector()
+        .map(|connector| connector.to_string())
+        .unwrap_or_else(|| "unknown monitor".to_owned())
+}
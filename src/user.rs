@@ -0,0 +1,190 @@
+use std::path::{Path, PathBuf};
+
+use gtk::gdk;
+use gtk::gdk_pixbuf::Pixbuf;
+use gtk::gio;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use log::{info, warn};
+use nix::unistd::{Uid, User};
+use zbus::proxy;
+use zbus::zvariant::OwnedObjectPath;
+
+/// Size of avatar in logical pixels
+const AVATAR_SIZE: i32 = 96;
+/// Avatars are decoded larger than shown to stay
+/// sharp on scaled monitors
+const AVATAR_DECODE_SCALE: i32 = 2;
+/// Picture of user set by display managers, relative to home
+const FACE_FILES: [&str; 2] = [".face", ".face.icon"];
+
+/// How user of locked session is presented,
+/// [`None`] if not known
+#[derive(Default)]
+struct UserInfo {
+    real_name: Option<String>,
+    avatar: Option<PathBuf>,
+}
+
+/// Avatar and real name of current user
+///
+/// Login name and generic avatar are shown until
+/// actual ones are looked up
+pub fn user() -> gtk::Widget {
+    let bbox = gtk::Box::builder()
+        .css_classes(["user"])
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .build();
+
+    let avatar = gtk::Image::builder()
+        .css_classes(["user-avatar"])
+        .icon_name("avatar-default-symbolic")
+        .pixel_size(AVATAR_SIZE)
+        .halign(gtk::Align::Center)
+        .overflow(gtk::Overflow::Hidden)
+        .build();
+    let name = gtk::Label::builder()
+        .css_classes(["user-name"])
+        .label(
+            users::get_current_username()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        )
+        .build();
+    bbox.append(&avatar);
+    bbox.append(&name);
+
+    glib::spawn_future_local(clone!(
+        #[weak]
+        avatar,
+        #[weak]
+        name,
+        async move {
+            let info = user_info().await;
+
+            if let Some(real_name) = info.real_name {
+                name.set_label(&real_name);
+            }
+            let Some(path) = info.avatar else {
+                return;
+            };
+            if let Some(texture) = load_avatar(&path).await {
+                avatar.set_paintable(Some(&texture));
+            }
+        }
+    ));
+
+    bbox.into()
+}
+
+/// Looked up in AccountsService, then in passwd
+/// database and home directory
+async fn user_info() -> UserInfo {
+    let uid = users::get_current_uid();
+
+    let accounts = match accounts_service_info(uid).await {
+        Ok(info) => info,
+        Err(err) => {
+            info!("AccountsService is not available: {err}");
+            UserInfo::default()
+        }
+    };
+    if accounts.real_name.is_some() && accounts.avatar.is_some() {
+        return accounts;
+    }
+
+    // Passwd may be stored on a network server
+    let fallback = gio::spawn_blocking(move || passwd_info(uid))
+        .await
+        .unwrap_or_default();
+
+    UserInfo {
+        real_name: accounts.real_name.or(fallback.real_name),
+        avatar: accounts.avatar.or(fallback.avatar),
+    }
+}
+
+async fn accounts_service_info(uid: u32) -> zbus::Result<UserInfo> {
+    let connection = zbus::Connection::system().await?;
+    let accounts = AccountsProxy::new(&connection).await?;
+    let path = accounts.find_user_by_id(uid.into()).await?;
+    let user = AccountsUserProxy::builder(&connection)
+        .path(path)?
+        .build()
+        .await?;
+
+    let real_name = user.real_name().await?;
+    let icon_file = user.icon_file().await?;
+
+    Ok(UserInfo {
+        real_name: non_empty(&real_name),
+        // Icon is reported even if user never set one
+        avatar: Some(PathBuf::from(icon_file)).filter(|path| path.is_file()),
+    })
+}
+
+fn passwd_info(uid: u32) -> UserInfo {
+    let user = match User::from_uid(Uid::from_raw(uid)) {
+        Ok(Some(user)) => user,
+        Ok(None) => return UserInfo::default(),
+        Err(err) => {
+            warn!("Failed to look up user {uid}: {err}");
+            return UserInfo::default();
+        }
+    };
+
+    // GECOS is a comma separated list starting with full name
+    let gecos = user.gecos.to_string_lossy();
+    let real_name = gecos.split(',').next().and_then(non_empty);
+    let avatar = FACE_FILES
+        .iter()
+        .map(|file| user.dir.join(file))
+        .find(|path| path.is_file());
+
+    UserInfo { real_name, avatar }
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_owned())
+}
+
+async fn load_avatar(path: &Path) -> Option<gdk::Texture> {
+    let size = AVATAR_SIZE * AVATAR_DECODE_SCALE;
+    let pixbuf = match gio::File::for_path(path)
+        .read_future(glib::Priority::DEFAULT)
+        .await
+    {
+        Ok(stream) => Pixbuf::from_stream_at_scale_future(&stream, size, size, true).await,
+        Err(err) => Err(err),
+    };
+
+    match pixbuf {
+        Ok(pixbuf) => Some(gdk::Texture::for_pixbuf(&pixbuf)),
+        Err(err) => {
+            warn!("Failed to load avatar {}: {err}", path.to_string_lossy());
+            None
+        }
+    }
+}
+
+#[proxy(
+    interface = "org.freedesktop.Accounts",
+    default_path = "/org/freedesktop/Accounts",
+    default_service = "org.freedesktop.Accounts"
+)]
+pub trait Accounts {
+    fn find_user_by_id(&self, id: i64) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.Accounts.User",
+    default_service = "org.freedesktop.Accounts"
+)]
+pub trait AccountsUser {
+    #[zbus(property)]
+    fn real_name(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn icon_file(&self) -> zbus::Result<String>;
+}